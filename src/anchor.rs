@@ -1,59 +1,930 @@
+use crate::base_chunk::ChunkLen;
+use crate::base_chunk::ChunkSize;
+use crate::base_chunk::Size;
+use crate::base_chunk::DEFAULT_CHUNK_SIZE;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::iter::FromIterator;
+use core::iter::FusedIterator;
 use core::marker::PhantomData;
-use std::mem::MaybeUninit;
+use core::mem::MaybeUninit;
 
-type Chunk<T> = crate::base_chunk::Chunk<T, Option<Box<()>>>;
+type Chunk<T, const SIZE: usize = DEFAULT_CHUNK_SIZE> =
+    crate::base_chunk::Chunk<T, Option<Box<()>>, SIZE>;
 
 /// Not really an index, just accesses the Chunks chained.
-/// Contains a pointer to the first Chunk and thats it.
+/// Contains a pointer to the first Chunk and a small index.
 ///
 /// This is just the "anchor" every interesting per-chunk
 /// operation is implemented on the Iterator.
 ///
-/// All operations defined directly on this that need to seek
-/// always start at the front for every single operation.
+/// Random access goes through the index, which knows where
+/// every chunk starts. Everything else that needs to seek
+/// always starts at the front for every single operation.
 ///
 /// Does not allocate until elements are actually pushed.
-pub struct Anchor<T> {
-    start: Option<Box<Chunk<T>>>,
+pub struct Anchor<T, const SIZE: usize = DEFAULT_CHUNK_SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    start: Option<Box<Chunk<T, SIZE>>>,
+    index: ChunkIndex<T, SIZE>,
+    /// fraction of its capacity every chunk should at least be filled to.
+    min_fill: f32,
+    /// fraction of its capacity chunks are filled to when loading in bulk.
+    bulk_fill: f32,
 }
 
-impl<T> Anchor<T> {
+/// what min_fill starts out as
+pub const DEFAULT_MIN_FILL: f32 = 0.25;
+
+/// what bulk_fill starts out as
+pub const DEFAULT_BULK_FILL: f32 = 1.0;
+
+/// minimum number of elements in a chunk of capacity, to be at least min_fill full.
+/// never 0, empty chunks are not worth keeping.
+fn min_len(min_fill: f32, capacity: usize) -> usize {
+    ((capacity as f32 * min_fill) as usize).max(1)
+}
+
+// the index only points into chunks owned by the anchor,
+// so it behaves just like the boxes.
+unsafe impl<T: Send, const SIZE: usize> Send for Anchor<T, SIZE> where Size<SIZE>: ChunkSize {}
+unsafe impl<T: Sync, const SIZE: usize> Sync for Anchor<T, SIZE> where Size<SIZE>: ChunkSize {}
+
+impl<T, const SIZE: usize> Anchor<T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
     pub fn new() -> Self {
-        Self { start: None }
+        Self {
+            start: None,
+            index: ChunkIndex::new(),
+            min_fill: DEFAULT_MIN_FILL,
+            bulk_fill: DEFAULT_BULK_FILL,
+        }
     }
 
     /// creates a new Anchor containing an allocated, but empty chunk.
     pub fn new_empty() -> Self {
-        let start = Box::new(Chunk::new(MaybeUninit::uninit()));
-        Self { start: Some(start) }
+        let start = Chunk::boxed();
+        let mut a = Self {
+            start: Some(start),
+            index: ChunkIndex::new(),
+            min_fill: DEFAULT_MIN_FILL,
+            bulk_fill: DEFAULT_BULK_FILL,
+        };
+        a.index.rebuild(&mut a.start);
+        a
+    }
+
+    /// iterates over all elements, chunk after chunk.
+    pub fn iter(&self) -> Iter<'_, T, SIZE> {
+        Iter {
+            chunks: self.chunks(),
+            current: [].iter(),
+            remaining: self.len(),
+        }
+    }
+
+    /// iterates mutably over all elements, chunk after chunk.
+    pub fn iter_mut(&mut self) -> IterMut<'_, T, SIZE> {
+        self.refresh_index();
+        let remaining = self.len();
+        IterMut {
+            chunks: self.index.chunks.iter(),
+            current: [].iter_mut(),
+            remaining,
+            _anchor: PhantomData,
+        }
+    }
+
+    /// iterates over the chunks themselves.
+    pub fn chunks(&self) -> AnchorIterator<'_, T, SIZE> {
+        AnchorIterator::new(self)
     }
 
     /// The regular Iterator interface can not be implemented by
     /// AnchorIteratorMut because it needs to enforce
     /// that each item is gone before the next is returned.
     /// The lifetimes around Iterator::next() do not allow for that.
-    pub fn iter_mut(&mut self) -> AnchorIteratorMut<T> {
+    ///
+    /// The index is brought up to date once the iterator is dropped.
+    /// If it is leaked instead the index is rebuilt by the next change,
+    /// until then len and get walk the chunks.
+    pub fn chunks_mut(&mut self) -> AnchorIteratorMut<'_, T, SIZE> {
         AnchorIteratorMut::new(self)
     }
+
+    /// fraction of its capacity every chunk, except for the last one,
+    /// is kept filled to after removals.
+    pub fn min_fill(&self) -> f32 {
+        self.min_fill
+    }
+
+    /// sets the fraction of its capacity every chunk, except for the last one,
+    /// is kept filled to after removals.
+    /// higher values waste less space but need to move more elements around on removal.
+    ///
+    /// only applies to future removals, call compact() to apply it to the whole list.
+    ///
+    /// panics if min_fill is not in 0.0..=0.5,
+    /// anything more can't be upheld when inserting into a full chunk.
+    pub fn set_min_fill(&mut self, min_fill: f32) {
+        assert!(
+            (0.0..=0.5).contains(&min_fill),
+            "min_fill needs to be between 0 and 0.5, is {}",
+            min_fill
+        );
+        self.min_fill = min_fill;
+    }
+
+    /// fraction of its capacity chunks are filled to by extend, extend_from_slice and from_vec.
+    pub fn bulk_fill(&self) -> f32 {
+        self.bulk_fill
+    }
+
+    /// sets the fraction of its capacity chunks are filled to when loading in bulk.
+    /// full chunks are the densest, but the first insert into each of them splits it.
+    ///
+    /// panics if bulk_fill is not in 0.5..=1.0, below that chunks would be merged again
+    /// by the first removal.
+    pub fn set_bulk_fill(&mut self, bulk_fill: f32) {
+        assert!(
+            (0.5..=1.0).contains(&bulk_fill),
+            "bulk_fill needs to be between 0.5 and 1, is {}",
+            bulk_fill
+        );
+        self.bulk_fill = bulk_fill;
+    }
+
+    /// merges and refills chunks until every chunk, except for the last one,
+    /// is at least min_fill full.
+    pub fn compact(&mut self) {
+        let min_fill = self.min_fill;
+        let mut iter = self.chunks_mut();
+        while let Some(chunk) = iter.next() {
+            let min = min_len(min_fill, chunk.capacity());
+            while let Some(next_len) = chunk.next_len() {
+                if (chunk.len() < min || next_len < min) && chunk.merge_next() {
+                    continue;
+                }
+                if chunk.len() < min {
+                    // they don't fit into one chunk, so next has more than enough to spare
+                    let count = min - chunk.len();
+                    assert!(chunk.steal_from_next(count));
+                }
+                break;
+            }
+        }
+    }
+
+    /// total number of elements in all chunks
+    pub fn len(&self) -> usize {
+        if self.index.stale {
+            return self.chunks().map(|c| c.len()).sum();
+        }
+        self.index.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// returns the element at position i, counted over all chunks.
+    /// does a binary search in the index, no need to walk the chunks.
+    pub fn get(&self, i: usize) -> Option<&T> {
+        if self.index.stale {
+            return self.iter().nth(i);
+        }
+        let (chunk, pos) = self.index.find(i)?;
+        // the index only contains pointers to our own chunks
+        let chunk = unsafe { self.index.chunks[chunk].1.as_ref() }.unwrap();
+        chunk.get(pos)
+    }
+
+    /// returns the element at position i, counted over all chunks.
+    /// does a binary search in the index, no need to walk the chunks.
+    pub fn get_mut(&mut self, i: usize) -> Option<&mut T> {
+        self.refresh_index();
+        let (chunk, pos) = self.index.find(i)?;
+        // the index only contains pointers to our own chunks
+        // and we have exclusive access to them.
+        let chunk = unsafe { self.index.chunks[chunk].1.as_mut() }.unwrap();
+        chunk.get_mut(pos)
+    }
+
+    pub fn first(&self) -> Option<&T> {
+        self.get(0)
+    }
+
+    pub fn first_mut(&mut self) -> Option<&mut T> {
+        self.get_mut(0)
+    }
+
+    pub fn last(&self) -> Option<&T> {
+        self.get(self.len().checked_sub(1)?)
+    }
+
+    pub fn last_mut(&mut self) -> Option<&mut T> {
+        self.get_mut(self.len().checked_sub(1)?)
+    }
+
+    /// rebuilds the index if an AnchorIteratorMut was leaked.
+    /// everything that uses the index with &mut self calls this first.
+    fn refresh_index(&mut self) {
+        if self.index.stale {
+            self.index.rebuild(&mut self.start);
+        }
+    }
+
+    /// the k-th chunk, as recorded in the index
+    fn chunk_mut(&mut self, k: usize) -> &mut Chunk<T, SIZE> {
+        // the index only contains pointers to our own chunks
+        // and we have exclusive access to them.
+        unsafe { self.index.chunks[k].1.as_mut() }.unwrap()
+    }
+
+    /// appends an element to the last chunk.
+    /// if that is full a new chunk is appended.
+    pub fn push(&mut self, element: T) {
+        self.refresh_index();
+        if self.start.is_none() {
+            self.start = Some(Chunk::boxed());
+            self.index.rebuild(&mut self.start);
+        }
+        let last = self.index.chunks.len() - 1;
+        let chunk = self.chunk_mut(last);
+        if let Some(element) = chunk.push(element) {
+            let mut new = Chunk::boxed();
+            // this will only fail if one element is bigger than a whole chunk
+            assert!(new.push(element).is_none());
+            let ptr = new.as_mut() as *mut Chunk<T, SIZE>;
+            chunk.next_hint = Some(new);
+            self.index.push(ptr);
+        }
+        self.index.len += 1;
+    }
+
+    /// appends up to count elements, filling the last chunk and then new ones to bulk_fill.
+    /// fill(chunk, n) appends up to n elements to chunk and returns how many it did,
+    /// fewer than n means there are no more.
+    fn append_dense<F>(&mut self, mut count: usize, mut fill: F)
+    where
+        F: FnMut(&mut Chunk<T, SIZE>, usize) -> usize,
+    {
+        if count == 0 {
+            return;
+        }
+        self.refresh_index();
+        if self.start.is_none() {
+            self.start = Some(Chunk::boxed());
+            self.index.rebuild(&mut self.start);
+        }
+        let bulk_fill = self.bulk_fill;
+        let mut k = self.index.chunks.len() - 1;
+        loop {
+            let chunk = self.chunk_mut(k);
+            let n = min_len(bulk_fill, chunk.capacity())
+                .saturating_sub(chunk.len())
+                .min(count);
+            let filled = fill(chunk, n);
+            self.index.len += filled;
+            count -= filled;
+            if filled < n || count == 0 {
+                return;
+            }
+
+            let mut new = Chunk::boxed();
+            let ptr = new.as_mut() as *mut Chunk<T, SIZE>;
+            self.chunk_mut(k).next_hint = Some(new);
+            self.index.push(ptr);
+            k += 1;
+        }
+    }
+
+    /// appends all elements of values, copying them into the chunks in bulk.
+    /// the chunks are filled to bulk_fill.
+    pub fn extend_from_slice(&mut self, values: &[T])
+    where
+        T: Copy,
+    {
+        let mut rest = values;
+        self.append_dense(values.len(), |chunk, n| {
+            let (head, tail) = rest.split_at(n);
+            rest = tail;
+            chunk.extend_from_slice(head)
+        });
+    }
+
+    /// moves the elements of vec into a new Anchor, copying them into the chunks in bulk.
+    /// the chunks are filled to DEFAULT_BULK_FILL.
+    pub fn from_vec(mut vec: Vec<T>) -> Self {
+        let mut a = Self::new();
+        let len = vec.len();
+        let mut values = vec.as_ptr();
+        // the elements are moved into the chunks, the vec only frees its buffer.
+        // if allocating a chunk panics the rest of them is leaked.
+        unsafe { vec.set_len(0) };
+        a.append_dense(len, |chunk, n| {
+            unsafe {
+                chunk.extend_from_raw(values, n);
+                values = values.add(n);
+            }
+            n
+        });
+        a
+    }
+
+    /// removes the last element.
+    /// empty chunks left at the end are freed, except for the first one.
+    pub fn pop(&mut self) -> Option<T> {
+        self.refresh_index();
+        let (k, _pos) = self.index.find(self.len().checked_sub(1)?)?;
+        let chunk = self.chunk_mut(k);
+        let element = chunk.pop();
+        // anything behind k is empty
+        chunk.next_hint = None;
+        let keep = if chunk.len() == 0 && k != 0 { k } else { k + 1 };
+        if keep == k {
+            self.chunk_mut(k - 1).next_hint = None;
+        }
+        self.index.chunks.truncate(keep);
+        self.index.len -= 1;
+        element
+    }
+
+    /// inserts element at position i, shifting all following elements back by one.
+    /// if the chunk at i is full it is split in half.
+    ///
+    /// panics if i > len.
+    pub fn insert(&mut self, i: usize, element: T) {
+        self.refresh_index();
+        let len = self.len();
+        assert!(
+            i <= len,
+            "insertion index {} is out of bounds for length {}",
+            i,
+            len
+        );
+        if i == len {
+            return self.push(element);
+        }
+
+        let (k, pos) = self.index.find(i).unwrap();
+        // going through the raw pointer so the index can be updated while holding on to the
+        // chunk. the index only contains pointers to our own chunks.
+        let chunk = unsafe { self.index.chunks[k].1.as_mut() }.unwrap();
+        let k = match chunk.insert(pos, element) {
+            Ok(_) => k,
+            Err(element) => {
+                let mid = chunk.len() / 2;
+                let chunk_mut: &mut ChunkMut<T, SIZE> = chunk.into();
+                chunk_mut.split(mid);
+                // split guarantees there is a next chunk
+                let new = chunk.next_hint.as_mut().unwrap().as_mut();
+                let new_start = self.index.chunks[k].0.wrapping_add(mid);
+                self.index
+                    .chunks
+                    .insert(k + 1, (new_start, new as *mut Chunk<T, SIZE>));
+
+                // both halves have space now
+                if pos <= mid {
+                    assert!(chunk.insert(pos, element).is_ok());
+                    k
+                } else {
+                    assert!(new.insert(pos - mid, element).is_ok());
+                    k + 1
+                }
+            }
+        };
+        self.index.grow(k);
+    }
+
+    /// removes and returns the element at position i,
+    /// shifting all following elements forward by one.
+    /// chunks that become empty are freed, except for the last remaining one.
+    ///
+    /// panics if i >= len.
+    pub fn remove(&mut self, i: usize) -> T {
+        self.refresh_index();
+        let len = self.len();
+        let (k, pos) = match self.index.find(i) {
+            Some(found) => found,
+            None => panic!("removal index {} is out of bounds for length {}", i, len),
+        };
+        let element = self.chunk_mut(k).remove(pos).unwrap();
+        self.index.shrink(k);
+        self.rebalance(k);
+        element
+    }
+
+    /// brings the k-th chunk back up to min_fill after a removal
+    /// by merging it with a neighbour or stealing from the next chunk.
+    /// the last chunk is allowed to stay underfull if it does not fit into the previous one.
+    fn rebalance(&mut self, k: usize) {
+        // going through the raw pointer so the index can be updated while holding on to the
+        // chunk. the index only contains pointers to our own chunks.
+        let chunk = unsafe { self.index.chunks[k].1.as_mut() }.unwrap();
+        let min = min_len(self.min_fill, chunk.capacity());
+        if chunk.len() >= min {
+            return;
+        }
+        let chunk: &mut ChunkMut<T, SIZE> = chunk.into();
+
+        if chunk.has_next() {
+            if chunk.merge_next() {
+                self.index.chunks.remove(k + 1);
+            } else {
+                // they don't fit into one chunk, so next has more than enough to spare
+                let count = min - chunk.len();
+                assert!(chunk.steal_from_next(count));
+                let start = &mut self.index.chunks[k + 1].0;
+                *start = start.wrapping_add(count);
+            }
+        } else if k > 0 {
+            let pre: &mut ChunkMut<T, SIZE> = self.chunk_mut(k - 1).into();
+            if pre.merge_next() {
+                self.index.chunks.remove(k);
+            }
+        }
+    }
+
+    /// moves everything from position at onwards into a new Anchor.
+    /// splits at most one chunk, the chunks behind it are just relinked.
+    /// their index entries are moved over in one piece and keep their starts,
+    /// the new index is shifted by at instead.
+    /// the chunks on both sides of the split are merged or refilled like after a removal.
+    ///
+    /// panics if at > len.
+    pub fn split_off(&mut self, at: usize) -> Anchor<T, SIZE> {
+        self.refresh_index();
+        let len = self.len();
+        assert!(
+            at <= len,
+            "split index {} is out of bounds for length {}",
+            at,
+            len
+        );
+        let mut other = Anchor::new();
+        other.min_fill = self.min_fill;
+        other.bulk_fill = self.bulk_fill;
+        let (k, pos) = match self.index.find(at) {
+            Some(found) => found,
+            None => return other,
+        };
+
+        // keep is the number of chunks that stay in self
+        let (mut start, keep) = if pos == 0 {
+            let link = if k == 0 {
+                &mut self.start
+            } else {
+                &mut self.chunk_mut(k - 1).next_hint
+            };
+            (link.take(), k)
+        } else {
+            let chunk = self.chunk_mut(k);
+            let chunk_mut: &mut ChunkMut<T, SIZE> = chunk.into();
+            chunk_mut.split(pos);
+            (chunk.next_hint.take(), k + 1)
+        };
+
+        other.index.chunks = self.index.chunks.split_off(k);
+        if pos != 0 {
+            // the front half stays, the back half of the split chunk is not in the index yet
+            self.index.chunks.push(other.index.chunks[0]);
+            let new = start.as_mut().unwrap().as_mut() as *mut Chunk<T, SIZE>;
+            other.index.chunks[0] = (at.wrapping_add(self.index.shift), new);
+        }
+        other.index.shift = self.index.shift.wrapping_add(at);
+        other.index.len = len - at;
+        self.index.len = at;
+        other.start = start;
+
+        if keep > 0 {
+            self.rebalance(keep - 1);
+        }
+        if !other.index.chunks.is_empty() {
+            other.rebalance(0);
+        }
+        other
+    }
+
+    /// moves all elements of other to the end of self, leaving other empty.
+    /// the chunks of other are just relinked, at most the two chunks where they meet
+    /// are merged or refilled to keep up min_fill.
+    /// only the index entries of whichever anchor has fewer chunks are re-offset,
+    /// so appending small anchors to a big one does not get slower as it grows.
+    pub fn append(&mut self, other: &mut Anchor<T, SIZE>) {
+        self.refresh_index();
+        other.refresh_index();
+        let start = match other.start.take() {
+            Some(start) => start,
+            None => return,
+        };
+        let len = self.len();
+        let last = match self.index.chunks.len().checked_sub(1) {
+            Some(last) => {
+                self.chunk_mut(last).next_hint = Some(start);
+                Some(last)
+            }
+            None => {
+                self.start = Some(start);
+                None
+            }
+        };
+        // turns a start in other's index into one in self's
+        let delta = len
+            .wrapping_add(self.index.shift)
+            .wrapping_sub(other.index.shift);
+        if other.index.chunks.len() <= self.index.chunks.len() {
+            for (start, _) in &mut other.index.chunks {
+                *start = start.wrapping_add(delta);
+            }
+        } else {
+            for (start, _) in &mut self.index.chunks {
+                *start = start.wrapping_sub(delta);
+            }
+            self.index.shift = other.index.shift.wrapping_sub(len);
+        }
+        self.index.chunks.append(&mut other.index.chunks);
+        self.index.len += other.index.len;
+        other.index.clear();
+        // the last chunk of self is not the last one any more
+        if let Some(last) = last {
+            self.rebalance(last);
+        }
+    }
+
+    /// a cursor pointing at the first element,
+    /// or the "ghost" non-element if the anchor is empty.
+    pub fn cursor_front_mut(&mut self) -> CursorMut<'_, T, SIZE> {
+        CursorMut {
+            anchor: self,
+            index: 0,
+        }
+    }
+
+    /// a cursor pointing at the last element,
+    /// or the "ghost" non-element if the anchor is empty.
+    pub fn cursor_back_mut(&mut self) -> CursorMut<'_, T, SIZE> {
+        let index = self.len().saturating_sub(1);
+        CursorMut {
+            anchor: self,
+            index,
+        }
+    }
 }
 
-impl<'a, T> IntoIterator for &'a Anchor<T> {
-    type Item = &'a Chunk<T>;
-    type IntoIter = AnchorIterator<'a, T>;
+impl<T, const SIZE: usize> Drop for Anchor<T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    /// frees the chunks one after the other,
+    /// letting the boxes drop each other would recurse once per chunk.
+    fn drop(&mut self) {
+        let mut next = self.start.take();
+        while let Some(mut chunk) = next {
+            next = chunk.next_hint.take();
+        }
+    }
+}
 
-    fn into_iter(self) -> <Self as std::iter::IntoIterator>::IntoIter {
-        AnchorIterator::new(self)
+impl<T, const SIZE: usize> core::ops::Index<usize> for Anchor<T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    type Output = T;
+    fn index(&self, i: usize) -> &T {
+        match self.get(i) {
+            Some(e) => e,
+            None => panic!("index {} out of bounds for length {}", i, self.len()),
+        }
+    }
+}
+
+impl<T, const SIZE: usize> core::ops::IndexMut<usize> for Anchor<T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    fn index_mut(&mut self, i: usize) -> &mut T {
+        let len = self.len();
+        match self.get_mut(i) {
+            Some(e) => e,
+            None => panic!("index {} out of bounds for length {}", i, len),
+        }
+    }
+}
+
+/// A cursor over the single elements of an Anchor, modeled after the LinkedList one.
+///
+/// Points either at an element or at the "ghost" non-element, which sits between
+/// the last and the first element.
+///
+/// The position is tracked as an index over all chunks,
+/// so it stays valid no matter how the chunks are split on insertion.
+pub struct CursorMut<'a, T, const SIZE: usize = DEFAULT_CHUNK_SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    anchor: &'a mut Anchor<T, SIZE>,
+    /// anchor.len() if this is pointing at the ghost
+    index: usize,
+}
+
+impl<'a, T, const SIZE: usize> CursorMut<'a, T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    /// position of the current element, None at the ghost.
+    pub fn index(&self) -> Option<usize> {
+        if self.index < self.anchor.len() {
+            Some(self.index)
+        } else {
+            None
+        }
+    }
+
+    /// moves to the next element.
+    /// moves from the last element to the ghost, and from the ghost to the first element.
+    pub fn move_next(&mut self) {
+        if self.index >= self.anchor.len() {
+            self.index = 0;
+        } else {
+            self.index += 1;
+        }
+    }
+
+    /// moves to the previous element.
+    /// moves from the first element to the ghost, and from the ghost to the last element.
+    pub fn move_prev(&mut self) {
+        if self.index == 0 {
+            self.index = self.anchor.len();
+        } else {
+            self.index -= 1;
+        }
+    }
+
+    /// the current element, None at the ghost.
+    pub fn current(&mut self) -> Option<&mut T> {
+        self.anchor.get_mut(self.index)
+    }
+
+    /// the element move_next would move to, without moving.
+    pub fn peek_next(&mut self) -> Option<&mut T> {
+        let next = match self.index() {
+            Some(i) => i + 1,
+            None => 0,
+        };
+        self.anchor.get_mut(next)
+    }
+
+    /// the element move_prev would move to, without moving.
+    pub fn peek_prev(&mut self) -> Option<&mut T> {
+        let prev = match self.index() {
+            Some(0) => return None,
+            Some(i) => i - 1,
+            None => self.anchor.len().checked_sub(1)?,
+        };
+        self.anchor.get_mut(prev)
+    }
+
+    /// inserts element behind the current one.
+    /// at the ghost it becomes the new first element.
+    pub fn insert_after(&mut self, element: T) {
+        match self.index() {
+            Some(i) => self.anchor.insert(i + 1, element),
+            None => {
+                self.anchor.insert(0, element);
+                // still pointing at the ghost
+                self.index += 1;
+            }
+        }
+    }
+
+    /// inserts element in front of the current one.
+    /// at the ghost it becomes the new last element.
+    pub fn insert_before(&mut self, element: T) {
+        self.anchor.insert(self.index, element);
+        self.index += 1;
+    }
+
+    /// removes the current element and moves to the one behind it.
+    /// does nothing at the ghost.
+    pub fn remove_current(&mut self) -> Option<T> {
+        self.index()?;
+        Some(self.anchor.remove(self.index))
+    }
+
+    /// moves everything behind the current element into a new Anchor.
+    /// at the ghost that is everything.
+    pub fn split_off_after(&mut self) -> Anchor<T, SIZE> {
+        match self.index() {
+            Some(i) => self.anchor.split_off(i + 1),
+            None => {
+                let other = self.anchor.split_off(0);
+                self.index = 0;
+                other
+            }
+        }
+    }
+}
+
+/// The small index used for random access.
+/// Knows where every chunk starts, counted in elements.
+struct ChunkIndex<T, const SIZE: usize = DEFAULT_CHUNK_SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    /// (position of the first element + shift, chunk) for every chunk, in list order
+    chunks: Vec<(usize, *mut Chunk<T, SIZE>)>,
+    /// added to every position, wrapping.
+    /// lets split_off and append move entries between indices without touching each of them.
+    shift: usize,
+    /// total number of elements
+    len: usize,
+    /// set while the chunks may be changed behind the index's back, see AnchorIteratorMut.
+    /// nothing else in here can be trusted then.
+    stale: bool,
+}
+
+impl<T, const SIZE: usize> ChunkIndex<T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    fn new() -> Self {
+        Self {
+            chunks: Vec::new(),
+            shift: 0,
+            len: 0,
+            stale: false,
+        }
+    }
+
+    /// walks all the chunks to recreate the index
+    fn rebuild(&mut self, start: &mut Option<Box<Chunk<T, SIZE>>>) {
+        self.chunks.clear();
+        self.shift = 0;
+        let mut len = 0;
+        let mut chunk = start.as_mut().map(|b| b.as_mut());
+        while let Some(c) = chunk {
+            self.chunks.push((len, c as *mut Chunk<T, SIZE>));
+            len += c.len();
+            chunk = c.next_hint.as_mut().map(|b| b.as_mut());
+        }
+        self.len = len;
+        self.stale = false;
+    }
+
+    /// appends a chunk starting behind the last element
+    fn push(&mut self, chunk: *mut Chunk<T, SIZE>) {
+        self.chunks.push((self.len.wrapping_add(self.shift), chunk));
+    }
+
+    /// records that an element was added to the k-th chunk
+    fn grow(&mut self, k: usize) {
+        for (start, _) in &mut self.chunks[k + 1..] {
+            *start = start.wrapping_add(1);
+        }
+        self.len += 1;
+    }
+
+    /// records that an element was removed from the k-th chunk
+    fn shrink(&mut self, k: usize) {
+        for (start, _) in &mut self.chunks[k + 1..] {
+            *start = start.wrapping_sub(1);
+        }
+        self.len -= 1;
+    }
+
+    /// empties the index until the next rebuild
+    fn clear(&mut self) {
+        self.chunks.clear();
+        self.shift = 0;
+        self.len = 0;
+    }
+
+    /// returns (chunk, position inside the chunk) of element i.
+    fn find(&self, i: usize) -> Option<(usize, usize)> {
+        if i >= self.len {
+            return None;
+        }
+        // the last chunk starting at or before i.
+        // empty chunks share their start with the next chunk, so they are skipped.
+        let chunk = self
+            .chunks
+            .binary_search_by(|(start, _)| {
+                if start.wrapping_sub(self.shift) <= i {
+                    core::cmp::Ordering::Less
+                } else {
+                    core::cmp::Ordering::Greater
+                }
+            })
+            .unwrap_err()
+            - 1;
+        Some((chunk, i - self.chunks[chunk].0.wrapping_sub(self.shift)))
     }
 }
 
-pub struct AnchorIterator<'a, T> {
+impl<'a, T, const SIZE: usize> IntoIterator for &'a Anchor<T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T, SIZE>;
+
+    fn into_iter(self) -> <Self as core::iter::IntoIterator>::IntoIter {
+        self.iter()
+    }
+}
+
+impl<T, const SIZE: usize> FromIterator<T> for Anchor<T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    /// fills the chunks to DEFAULT_BULK_FILL.
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut a = Self::new();
+        a.extend(iter);
+        a
+    }
+}
+
+impl<T, const SIZE: usize> Extend<T> for Anchor<T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    /// appends the elements in bulk, the chunks are filled to bulk_fill.
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let mut iter = iter.into_iter().peekable();
+        while iter.peek().is_some() {
+            // the lower bound is only a hint, append_dense stops early if it was wrong
+            let (lower, _) = iter.size_hint();
+            self.append_dense(lower.max(1), |chunk, n| {
+                let mut filled = 0;
+                for e in iter.by_ref().take(n) {
+                    chunk.push(e);
+                    filled += 1;
+                }
+                filled
+            });
+        }
+    }
+}
+
+impl<'a, T: Copy + 'a, const SIZE: usize> Extend<&'a T> for Anchor<T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    fn extend<I: IntoIterator<Item = &'a T>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().copied())
+    }
+}
+
+impl<'a, T, const SIZE: usize> IntoIterator for &'a mut Anchor<T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    type Item = &'a mut T;
+    type IntoIter = IterMut<'a, T, SIZE>;
+
+    fn into_iter(self) -> <Self as core::iter::IntoIterator>::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<T, const SIZE: usize> IntoIterator for Anchor<T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    type Item = T;
+    type IntoIter = IntoIter<T, SIZE>;
+
+    fn into_iter(mut self) -> <Self as core::iter::IntoIterator>::IntoIter {
+        // len might walk the chunks, so ask before taking them
+        let remaining = self.len();
+        IntoIter {
+            chunk: self.start.take(),
+            pos: 0,
+            remaining,
+        }
+    }
+}
+
+pub struct AnchorIterator<'a, T, const SIZE: usize = DEFAULT_CHUNK_SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
     // we just keep the index around for lifetime reasons
-    _index: PhantomData<&'a Anchor<T>>,
-    chunk: Option<&'a Chunk<T>>,
+    _index: PhantomData<&'a Anchor<T, SIZE>>,
+    chunk: Option<&'a Chunk<T, SIZE>>,
 }
 
-impl<'a, T> AnchorIterator<'a, T> {
-    pub fn new(index: &'a Anchor<T>) -> Self {
+impl<'a, T, const SIZE: usize> AnchorIterator<'a, T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    pub fn new(index: &'a Anchor<T, SIZE>) -> Self {
         let chunk = index.start.as_ref().map(|b| b.as_ref());
         Self {
             chunk: chunk,
@@ -62,9 +933,12 @@ impl<'a, T> AnchorIterator<'a, T> {
     }
 }
 
-impl<'a, T> Iterator for AnchorIterator<'a, T> {
-    type Item = &'a Chunk<T>;
-    fn next(&mut self) -> Option<&'a Chunk<T>> {
+impl<'a, T, const SIZE: usize> Iterator for AnchorIterator<'a, T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    type Item = &'a Chunk<T, SIZE>;
+    fn next(&mut self) -> Option<&'a Chunk<T, SIZE>> {
         if let Some(chunk) = self.chunk {
             // inside a Anchor Chunks contain a pointer as their next_hint.
             self.chunk = chunk.next_hint.as_ref().map(|b| b.as_ref());
@@ -75,22 +949,227 @@ impl<'a, T> Iterator for AnchorIterator<'a, T> {
     }
 }
 
+/// Iterator over the elements of an Anchor.
+///
+/// Knows how many elements are left, so nth skips whole chunks
+/// using their len, without looking at their elements.
+pub struct Iter<'a, T, const SIZE: usize = DEFAULT_CHUNK_SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    chunks: AnchorIterator<'a, T, SIZE>,
+    current: core::slice::Iter<'a, T>,
+    /// elements left in current and the chunks behind it
+    remaining: usize,
+}
+
+impl<'a, T, const SIZE: usize> Iterator for Iter<'a, T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> {
+        self.nth(0)
+    }
+
+    fn nth(&mut self, mut n: usize) -> Option<&'a T> {
+        if n >= self.remaining {
+            // no need to walk the rest of the chunks, they are empty
+            self.remaining = 0;
+            self.current = [].iter();
+            return None;
+        }
+        self.remaining -= n + 1;
+        while n >= self.current.len() {
+            n -= self.current.len();
+            // remaining says there are more elements behind current
+            self.current = self.chunks.next().unwrap().iter();
+        }
+        self.current.nth(n)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, T, const SIZE: usize> ExactSizeIterator for Iter<'a, T, SIZE> where Size<SIZE>: ChunkSize {}
+impl<'a, T, const SIZE: usize> FusedIterator for Iter<'a, T, SIZE> where Size<SIZE>: ChunkSize {}
+
+/// Mutable iterator over the elements of an Anchor.
+///
+/// Walks the chunks through the index, which stays valid as no chunk can change
+/// while the anchor is borrowed.
+pub struct IterMut<'a, T, const SIZE: usize = DEFAULT_CHUNK_SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    chunks: core::slice::Iter<'a, (usize, *mut Chunk<T, SIZE>)>,
+    current: core::slice::IterMut<'a, T>,
+    /// elements left in current and the chunks behind it
+    remaining: usize,
+    _anchor: PhantomData<&'a mut Anchor<T, SIZE>>,
+}
+
+// hands out every element once, just like a slice::IterMut.
+unsafe impl<'a, T: Send, const SIZE: usize> Send for IterMut<'a, T, SIZE> where Size<SIZE>: ChunkSize
+{}
+unsafe impl<'a, T: Sync, const SIZE: usize> Sync for IterMut<'a, T, SIZE> where Size<SIZE>: ChunkSize
+{}
+
+impl<'a, T, const SIZE: usize> Iterator for IterMut<'a, T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    type Item = &'a mut T;
+    fn next(&mut self) -> Option<&'a mut T> {
+        self.nth(0)
+    }
+
+    fn nth(&mut self, mut n: usize) -> Option<&'a mut T> {
+        if n >= self.remaining {
+            self.remaining = 0;
+            self.current = [].iter_mut();
+            return None;
+        }
+        self.remaining -= n + 1;
+        while n >= self.current.len() {
+            n -= self.current.len();
+            // remaining says there are more elements behind current.
+            // the index only contains pointers to the anchors own chunks,
+            // each of them is visited once.
+            let (_start, chunk) = self.chunks.next().unwrap();
+            self.current = unsafe { chunk.as_mut() }.unwrap().iter_mut();
+        }
+        self.current.nth(n)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, T, const SIZE: usize> ExactSizeIterator for IterMut<'a, T, SIZE> where Size<SIZE>: ChunkSize
+{}
+impl<'a, T, const SIZE: usize> FusedIterator for IterMut<'a, T, SIZE> where Size<SIZE>: ChunkSize {}
+
+/// Iterator moving the elements out of an Anchor.
+///
+/// Frees every chunk as soon as it is used up,
+/// the elements that are not taken are dropped along with the iterator.
+pub struct IntoIter<T, const SIZE: usize = DEFAULT_CHUNK_SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    /// the first pos elements of chunk have already been moved out
+    chunk: Option<Box<Chunk<T, SIZE>>>,
+    pos: usize,
+    /// elements left in chunk and the chunks behind it
+    remaining: usize,
+}
+
+impl<T, const SIZE: usize> IntoIter<T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    /// drops what is left of the current chunk and moves on to the next one.
+    /// returns false if there is no current chunk.
+    fn next_chunk(&mut self) -> bool {
+        let mut chunk = match self.chunk.take() {
+            Some(chunk) => chunk,
+            None => return false,
+        };
+        let len = chunk.len();
+        let pos = self.pos;
+        self.pos = 0;
+        self.chunk = chunk.next_hint.take();
+        // the first pos elements are gone already, so the chunk can't drop them itself.
+        // if a drop panics the rest is leaked.
+        unsafe {
+            *Chunk::len_ptr(chunk.as_mut()) = ChunkLen::from_usize(0);
+            let rest = &mut chunk.as_uninit_slice_mut()[pos..len];
+            core::ptr::drop_in_place(rest as *mut [MaybeUninit<T>] as *mut [T]);
+        }
+        true
+    }
+}
+
+impl<T, const SIZE: usize> Iterator for IntoIter<T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.nth(0)
+    }
+
+    fn nth(&mut self, mut n: usize) -> Option<T> {
+        if n >= self.remaining {
+            while self.next_chunk() {}
+            self.remaining = 0;
+            return None;
+        }
+        self.remaining -= n + 1;
+        loop {
+            // remaining says there are more elements
+            let chunk = self.chunk.as_mut().unwrap();
+            let left = chunk.len() - self.pos;
+            if n < left {
+                let start = self.pos;
+                self.pos += n + 1;
+                // the elements from start on are still there, the chunk does not drop them.
+                // if a drop panics the element is leaked.
+                let values = chunk.as_uninit_slice_mut();
+                unsafe {
+                    let skipped = &mut values[start..start + n];
+                    core::ptr::drop_in_place(skipped as *mut [MaybeUninit<T>] as *mut [T]);
+                    return Some(values[start + n].as_ptr().read());
+                }
+            }
+            n -= left;
+            self.next_chunk();
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<T, const SIZE: usize> ExactSizeIterator for IntoIter<T, SIZE> where Size<SIZE>: ChunkSize {}
+impl<T, const SIZE: usize> FusedIterator for IntoIter<T, SIZE> where Size<SIZE>: ChunkSize {}
+
+impl<T, const SIZE: usize> Drop for IntoIter<T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    fn drop(&mut self) {
+        while self.next_chunk() {}
+    }
+}
+
 #[repr(transparent)]
-pub struct ChunkMut<T> {
-    chunk: Chunk<T>,
+pub struct ChunkMut<T, const SIZE: usize = DEFAULT_CHUNK_SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    chunk: Chunk<T, SIZE>,
 }
 
-impl<T> ChunkMut<T> {
+impl<T, const SIZE: usize> ChunkMut<T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
     /// splits this chunk at the specified position
     /// allocates a new chunk
     /// puts pointer to new chunk in next_hint field of current chunk.
     pub fn split(&mut self, pos: usize) {
-        let mut chunk = Box::new(MaybeUninit::uninit());
+        let mut chunk = Box::new_uninit();
         self.chunk.split(pos, chunk.as_mut());
         // split guarantees initialization.
         let mut chunk = unsafe { chunk.assume_init() };
         // time to fix up the pointers
-        std::mem::swap(&mut self.chunk.next_hint, &mut chunk.next_hint);
+        core::mem::swap(&mut self.chunk.next_hint, &mut chunk.next_hint);
         self.chunk.next_hint = Some(chunk);
     }
 
@@ -105,7 +1184,7 @@ impl<T> ChunkMut<T> {
             let next_ref = self.chunk.next_hint.as_mut().unwrap().as_mut();
             // this will only fail if one element is bigger than a whole chunk
             // which would be pointless.
-            next_ref.push(element).unwrap();
+            assert!(next_ref.push(element).is_none());
         } else {
             // we are good, the first push worked
         }
@@ -115,11 +1194,60 @@ impl<T> ChunkMut<T> {
         use crate::base_chunk::Link;
         !self.chunk.next_hint.is_empty()
     }
+
+    /// number of elements in this chunk
+    pub fn len(&self) -> usize {
+        self.chunk.len()
+    }
+
+    /// total (not remaining) capacity of this chunk
+    pub fn capacity(&self) -> usize {
+        self.chunk.capacity()
+    }
+
+    fn next_len(&self) -> Option<usize> {
+        self.chunk.next_hint.as_ref().map(|next| next.len())
+    }
+
+    /// moves all elements of the next chunk into this one and frees the next chunk.
+    /// returns false, and does nothing, if there is no next chunk or they don't fit into one.
+    pub fn merge_next(&mut self) -> bool {
+        let mut next = match self.chunk.next_hint.take() {
+            Some(next) => next,
+            None => return false,
+        };
+        if self.chunk.append(&mut next) {
+            // next is empty now, and freed at the end of this block
+            self.chunk.next_hint = next.next_hint.take();
+            true
+        } else {
+            self.chunk.next_hint = Some(next);
+            false
+        }
+    }
+
+    /// moves count elements from the front of the next chunk to the end of this one.
+    /// returns false, and does nothing, if there is no next chunk,
+    /// it has less than count elements, or they don't fit into this one.
+    ///
+    /// the next chunk is kept around, even if it ends up empty.
+    pub fn steal_from_next(&mut self, count: usize) -> bool {
+        let mut next = match self.chunk.next_hint.take() {
+            Some(next) => next,
+            None => return false,
+        };
+        let stolen = self.chunk.take_front(&mut next, count);
+        self.chunk.next_hint = Some(next);
+        stolen
+    }
 }
 
-impl<'a, T> From<&'a mut Chunk<T>> for &'a mut ChunkMut<T> {
-    fn from(other: &'a mut Chunk<T>) -> Self {
-        let ptr = other as *mut _ as *mut ChunkMut<T>;
+impl<'a, T, const SIZE: usize> From<&'a mut Chunk<T, SIZE>> for &'a mut ChunkMut<T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    fn from(other: &'a mut Chunk<T, SIZE>) -> Self {
+        let ptr = other as *mut _ as *mut ChunkMut<T, SIZE>;
         // this is safe, ChunkMut is transparent
         // and very much written around the idea of
         // being cast from/to Chunk
@@ -127,28 +1255,57 @@ impl<'a, T> From<&'a mut Chunk<T>> for &'a mut ChunkMut<T> {
     }
 }
 
-pub struct AnchorIteratorMut<'a, T> {
-    /// we just keep the index around for lifetime reasons
-    _index: PhantomData<&'a mut Anchor<T>>,
+pub struct AnchorIteratorMut<'a, T, const SIZE: usize = DEFAULT_CHUNK_SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    /// we keep the anchor around to fix up its index once we are done.
+    /// the chunks may be split at will in the meantime, the index is marked stale until then.
+    anchor: *mut Anchor<T, SIZE>,
+    _index: PhantomData<&'a mut Anchor<T, SIZE>>,
     /// chunk is always the _current_, i.e. last returned, chunk
     /// this is different from most iterators.
     /// we need that so if the chunk is modified and split
     /// this iterator still catches the newly created chunk
-    chunk: Option<&'a mut Chunk<T>>,
+    chunk: Option<&'a mut Chunk<T, SIZE>>,
     first: bool,
 }
 
-impl<'a, T> AnchorIteratorMut<'a, T> {
-    pub fn new(index: &'a mut Anchor<T>) -> Self {
-        let chunk = index.start.as_mut().map(|b| b.as_mut());
+impl<'a, T, const SIZE: usize> AnchorIteratorMut<'a, T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    pub fn new(index: &'a mut Anchor<T, SIZE>) -> Self {
+        // the index is invalidated by any change to the chunks.
+        // if we are leaked it stays stale until the anchor rebuilds it.
+        index.index.stale = true;
+        let anchor = index as *mut Anchor<T, SIZE>;
+        // we are the only ones using the anchor until we are dropped
+        let chunk = unsafe { (*anchor).start.as_mut() }.map(|b| b.as_mut());
         Self {
+            anchor,
             chunk,
             _index: Default::default(),
             first: true,
         }
     }
 }
-impl<'a, T> AnchorIteratorMut<'a, T> {
+
+impl<'a, T, const SIZE: usize> Drop for AnchorIteratorMut<'a, T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    fn drop(&mut self) {
+        // the chunk reference is never used again
+        // so we are back to having exclusive access to the anchor.
+        let anchor = unsafe { self.anchor.as_mut() }.unwrap();
+        anchor.index.rebuild(&mut anchor.start);
+    }
+}
+impl<'a, T, const SIZE: usize> AnchorIteratorMut<'a, T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
     /// This method is sightly different from a regular iterators next method:
     /// it takes &'b mut self instead of &mut self.
     /// This forces the user to let go of each returned value before requesting the next.
@@ -156,7 +1313,7 @@ impl<'a, T> AnchorIteratorMut<'a, T> {
     /// As such the following use is a compile-time error:
     /// ```compile_fail
     ///  let mut a: Anchor<u8> = Anchor::new_empty();
-    ///  let mut i: AnchorIteratorMut<_> = a.iter_mut();
+    ///  let mut i: AnchorIteratorMut<_> = a.chunks_mut();
     ///  let n = i.next().unwrap();
     ///  n.split(0);
     ///  let n = i.next().unwrap();
@@ -172,12 +1329,12 @@ impl<'a, T> AnchorIteratorMut<'a, T> {
     /// ```
     /// # use chunk_list::anchor::Anchor;
     /// let mut a: Anchor<u8> = Anchor::new_empty();
-    /// let mut i = a.iter_mut();
+    /// let mut i = a.chunks_mut();
     /// while let Some(chunk) = i.next() {
     ///     // your code here
     /// }
     /// ```
-    pub fn next<'b>(&'b mut self) -> Option<&'b mut ChunkMut<T>> {
+    pub fn next<'b>(&'b mut self) -> Option<&'b mut ChunkMut<T, SIZE>> {
         if self.chunk.is_some() {
             if self.first {
                 // don't move forwards, just return
@@ -185,7 +1342,7 @@ impl<'a, T> AnchorIteratorMut<'a, T> {
             } else {
                 // todo: this code feels overly complicated
                 let mut chunk = None;
-                std::mem::swap(&mut chunk, &mut self.chunk);
+                core::mem::swap(&mut chunk, &mut self.chunk);
                 let chunk = chunk.unwrap();
                 let hint = chunk.next_hint.as_mut();
                 let hint = hint.map(|b| b.as_mut());
@@ -198,12 +1355,15 @@ impl<'a, T> AnchorIteratorMut<'a, T> {
     }
 
     /// returns the current chunk without advancing the iterator
-    pub fn get<'b>(&'b mut self) -> Option<&'b mut ChunkMut<T>> {
+    pub fn get<'b>(&'b mut self) -> Option<&'b mut ChunkMut<T, SIZE>> {
         self.chunk.as_mut().map(|c| (*c).into())
     }
 }
 // separating the non-iterator functions for clarity
-impl<'a, T> AnchorIteratorMut<'a, T> {
+impl<'a, T, const SIZE: usize> AnchorIteratorMut<'a, T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
     /// searches for needle in all the chunks past the current
     ///
     /// if there are repeats, any element might be found.
@@ -231,7 +1391,7 @@ impl<'a, T> AnchorIteratorMut<'a, T> {
     /// todo: try harder to return the first match/stay in the first chunk
     pub fn search<'b>(&'b mut self, needle: &T) -> Result<(usize, usize), (usize, usize)>
     where
-        T: std::cmp::Ord,
+        T: core::cmp::Ord,
     {
         let mut past_min = false;
         let mut count = 0;
@@ -249,7 +1409,7 @@ impl<'a, T> AnchorIteratorMut<'a, T> {
 
             if past_min && needle <= last {
                 // this is for polonius
-                let chunk: &mut ChunkMut<T> = &mut *chunk;
+                let chunk: &mut ChunkMut<T, SIZE> = &mut *chunk;
                 match chunk.chunk.binary_search(needle) {
                     Ok(pos) => return Ok((count, pos)),
                     Err(pos) => return Err((count, pos)),
@@ -269,21 +1429,21 @@ impl<'a, T> AnchorIteratorMut<'a, T> {
 #[test]
 fn iter() {
     let a: Anchor<u8> = Anchor::new();
-    let mut i = (&a).into_iter();
+    let mut i = a.chunks();
     assert!(i.next().is_none());
 }
 
 #[test]
 fn iter_empty() {
     let a: Anchor<u8> = Anchor::new_empty();
-    let mut i: AnchorIterator<_> = (&a).into_iter();
+    let mut i: AnchorIterator<_> = a.chunks();
     assert!(i.next().is_some());
 }
 
 #[test]
 fn iter_mut() {
     let mut a: Anchor<u8> = Anchor::new_empty();
-    let mut i: AnchorIteratorMut<_> = a.iter_mut();
+    let mut i: AnchorIteratorMut<_> = a.chunks_mut();
     let n = i.next().unwrap();
     n.split(0);
     let n = i.next().unwrap();
@@ -291,3 +1451,465 @@ fn iter_mut() {
     assert!(i.next().is_some());
     assert!(i.next().is_none());
 }
+
+#[test]
+fn index() {
+    let a: Anchor<usize> = Anchor::new();
+    assert_eq!(a.len(), 0);
+    assert_eq!(a.get(0), None);
+
+    let mut a: Anchor<usize> = Anchor::new_empty();
+    assert!(a.is_empty());
+    {
+        let mut i = a.chunks_mut();
+        let chunk = i.next().unwrap();
+        for e in 0..2000 {
+            chunk.push(e);
+        }
+    }
+    // push splits off one element at a time, so lots of chunks
+    assert!(a.chunks().count() > 2);
+    {
+        // create some empty chunks as well
+        let mut i = a.chunks_mut();
+        i.next().unwrap().split(0);
+        let chunk = i.next().unwrap();
+        let len = chunk.chunk.len();
+        chunk.split(len);
+    }
+    assert_eq!(a.len(), 2000);
+    let flat: Vec<usize> = a.iter().copied().collect();
+    for (i, e) in flat.iter().enumerate() {
+        assert_eq!(a.get(i), Some(e));
+        assert_eq!(a[i], *e);
+    }
+    assert_eq!(a.get(2000), None);
+
+    *a.get_mut(1500).unwrap() = 0;
+    a[1501] = 1;
+    assert_eq!(a[1500], 0);
+    assert_eq!(a[1501], 1);
+}
+
+#[test]
+fn leaked_chunks_mut() {
+    let mut a: Anchor<usize> = (0..1000).collect();
+    let mut i = a.chunks_mut();
+    let chunk = i.next().unwrap();
+    chunk.split(10);
+    chunk.merge_next();
+    chunk.split(5);
+    core::mem::forget(i);
+
+    // nothing looks at the index until it is rebuilt
+    assert_eq!(a.len(), 1000);
+    assert_eq!(a.get(7), Some(&7));
+    assert_eq!(a[999], 999);
+    assert!(a.iter().copied().eq(0..1000));
+    a.push(1000);
+    assert_eq!(a.len(), 1001);
+    assert_eq!(a.get_mut(1000), Some(&mut 1000));
+    assert!(a.iter().copied().eq(0..1001));
+}
+
+#[test]
+#[should_panic]
+fn index_oob() {
+    let a: Anchor<usize> = Anchor::new_empty();
+    let _ = a[0];
+}
+
+#[test]
+fn vec_like() {
+    use rand::Rng;
+    let mut rng = rand::thread_rng();
+    let mut a: Anchor<u64> = Anchor::new();
+    let mut model: Vec<u64> = Vec::new();
+    assert_eq!(a.pop(), None);
+    assert_eq!(a.first(), None);
+    assert_eq!(a.last(), None);
+
+    for i in 0..3000 {
+        a.push(i);
+        model.push(i);
+    }
+    for i in 0..20000 {
+        match rng.gen_range(0, 4) {
+            0 => {
+                let pos = rng.gen_range(0, model.len() + 1);
+                a.insert(pos, i);
+                model.insert(pos, i);
+            }
+            1 if !model.is_empty() => {
+                let pos = rng.gen_range(0, model.len());
+                assert_eq!(a.remove(pos), model.remove(pos));
+            }
+            2 => assert_eq!(a.pop(), model.pop()),
+            _ => {
+                a.push(i);
+                model.push(i);
+            }
+        }
+        assert_eq!(a.len(), model.len());
+    }
+    assert_eq!(a.first(), model.first());
+    assert_eq!(a.last(), model.last());
+    let flat: Vec<u64> = a.iter().copied().collect();
+    assert_eq!(flat, model);
+    for (i, e) in model.iter().enumerate() {
+        assert_eq!(&a[i], e);
+    }
+
+    while let Some(e) = a.pop() {
+        assert_eq!(Some(e), model.pop());
+    }
+    assert!(model.is_empty());
+    assert_eq!(a.chunks().count(), 1);
+}
+
+#[test]
+fn cursor() {
+    let mut a: Anchor<u64> = Anchor::new();
+    let mut model: Vec<u64> = Vec::new();
+    {
+        let mut c = a.cursor_front_mut();
+        assert_eq!(c.index(), None);
+        assert_eq!(c.current(), None);
+        assert_eq!(c.remove_current(), None);
+        c.insert_before(0);
+        c.insert_after(1);
+        assert_eq!(c.index(), None);
+        assert_eq!(c.peek_next(), Some(&mut 1));
+        assert_eq!(c.peek_prev(), Some(&mut 0));
+    }
+    model.extend_from_slice(&[1, 0]);
+
+    // walk the list, inserting behind every element until it spans lots of chunks
+    {
+        let mut c = a.cursor_front_mut();
+        let mut pos = 0;
+        for i in 2..3000 {
+            c.insert_after(i);
+            model.insert(pos + 1, i);
+            if i % 3 == 0 {
+                c.move_next();
+                pos += 1;
+            }
+            assert_eq!(c.index(), Some(pos));
+            assert_eq!(c.current(), Some(&mut model[pos]));
+        }
+
+        // and remove every third one, going backwards
+        while c.index().is_some() {
+            if c.index().unwrap() % 3 == 0 {
+                let pos = c.index().unwrap();
+                assert_eq!(c.remove_current(), Some(model.remove(pos)));
+            }
+            c.move_prev();
+        }
+    }
+    let flat: Vec<u64> = a.iter().copied().collect();
+    assert_eq!(flat, model);
+
+    let mut c = a.cursor_back_mut();
+    assert_eq!(c.current().copied(), model.last().copied());
+    for _ in 0..1234 {
+        c.move_prev();
+    }
+    let tail = c.split_off_after();
+    let model_tail = model.split_off(model.len() - 1234);
+    assert_eq!(c.peek_next(), None);
+    let flat: Vec<u64> = tail.iter().copied().collect();
+    assert_eq!(flat, model_tail);
+    assert_eq!(tail.len(), model_tail.len());
+    assert_eq!(a.len(), model.len());
+    assert_eq!(a.last(), model.last());
+
+    let mut c = a.cursor_front_mut();
+    c.move_prev();
+    let all = c.split_off_after();
+    assert_eq!(all.len(), model.len());
+    assert_eq!(a.len(), 0);
+    assert_eq!(a.first(), None);
+}
+
+#[test]
+fn merge_steal() {
+    let mut a: Anchor<u64> = Anchor::new_empty();
+    let mut i = a.chunks_mut();
+    let chunk = i.next().unwrap();
+    for e in 0..10 {
+        chunk.push(e);
+    }
+    chunk.split(4);
+    assert!(chunk.steal_from_next(2));
+    assert!(!chunk.steal_from_next(5));
+    assert_eq!(chunk.next_len(), Some(4));
+    assert!(chunk.merge_next());
+    assert!(!chunk.merge_next());
+    assert!(!chunk.steal_from_next(1));
+    assert_eq!(chunk.len(), 10);
+    drop(i);
+    assert_eq!(a.len(), 10);
+    assert_eq!(a.chunks().count(), 1);
+}
+
+#[test]
+fn min_fill() {
+    fn check_fill(a: &Anchor<u64>) {
+        let chunks: Vec<_> = a.chunks().collect();
+        let min = min_len(a.min_fill(), chunks[0].capacity());
+        for chunk in &chunks[..chunks.len() - 1] {
+            assert!(chunk.len() >= min);
+        }
+    }
+    use rand::Rng;
+    let mut rng = rand::thread_rng();
+
+    // pushing into the first chunk splits off lots of tiny chunks
+    let mut a: Anchor<u64> = Anchor::new_empty();
+    {
+        let mut i = a.chunks_mut();
+        let chunk = i.next().unwrap();
+        for e in 0..3000 {
+            chunk.push(e);
+        }
+    }
+    let before: Vec<u64> = a.iter().copied().collect();
+    let chunks = a.chunks().count();
+    a.set_min_fill(0.5);
+    a.compact();
+    check_fill(&a);
+    let after: Vec<u64> = a.iter().copied().collect();
+    assert_eq!(before, after);
+    assert!(a.chunks().count() < chunks);
+    for (i, e) in after.iter().enumerate() {
+        assert_eq!(&a[i], e);
+    }
+
+    let mut model = after;
+    while !model.is_empty() {
+        let pos = rng.gen_range(0, model.len());
+        assert_eq!(a.remove(pos), model.remove(pos));
+        check_fill(&a);
+    }
+    assert_eq!(a.chunks().count(), 1);
+}
+
+#[test]
+fn chunk_sizes() {
+    let mut small: Anchor<u64, 512> = Anchor::new();
+    let mut big: Anchor<u64, 65536> = Anchor::new();
+    for e in 0..2000 {
+        small.push(e);
+        big.push(e);
+    }
+    small.insert(1000, 5);
+    big.insert(1000, 5);
+    assert_eq!(small.len(), 2001);
+    assert!(small.chunks().count() > big.chunks().count());
+    for i in 0..2001 {
+        assert_eq!(small[i], big[i]);
+    }
+    assert_eq!(small.remove(1000), 5);
+    assert_eq!(small[1999], 1999);
+}
+
+#[test]
+fn elements() {
+    let mut a: Anchor<u64, 512> = Anchor::new();
+    for e in 0..1000 {
+        a.push(e);
+    }
+    // an empty chunk in front
+    a.chunks_mut().next().unwrap().split(0);
+    assert!(a.chunks().count() > 10);
+
+    let mut i = a.iter();
+    assert_eq!(i.len(), 1000);
+    assert_eq!(i.next(), Some(&0));
+    assert_eq!(i.nth(500), Some(&501));
+    assert_eq!(i.len(), 498);
+    assert!(i.copied().eq(502..1000));
+    assert!(a.iter().skip(990).copied().eq(990..1000));
+    let mut i = a.iter();
+    assert_eq!(i.nth(1000), None);
+    assert_eq!(i.next(), None);
+    assert_eq!(i.len(), 0);
+
+    for e in &mut a {
+        *e *= 2;
+    }
+    assert_eq!(a.iter_mut().nth(999), Some(&mut 1998));
+    assert!(a.iter().copied().eq((0..1000).map(|e| e * 2)));
+
+    // by value, the elements that are not taken are dropped with the iterator
+    let rc = std::rc::Rc::new(());
+    let mut a: Anchor<std::rc::Rc<()>, 512> = Anchor::new();
+    for _ in 0..1000 {
+        a.push(rc.clone());
+    }
+    let mut i = a.into_iter();
+    assert!(i.next().is_some());
+    assert!(i.nth(700).is_some());
+    assert_eq!(i.len(), 298);
+    assert_eq!(std::rc::Rc::strong_count(&rc), 1 + 298);
+    drop(i);
+    assert_eq!(std::rc::Rc::strong_count(&rc), 1);
+}
+
+#[test]
+fn into_iter_len() {
+    let mut a: Anchor<u64, 512> = (0..1000).collect();
+    for i in (0..500).rev() {
+        a.remove(i * 2);
+    }
+    a.insert(3, 7);
+    assert!(a.chunks().count() > 2);
+    let len = a.len();
+    let i = a.into_iter();
+    assert_eq!(i.size_hint(), (len, Some(len)));
+    assert_eq!(i.count(), len);
+
+    // the index is stale, len walks the chunks
+    let mut a: Anchor<u64, 512> = (0..1000).collect();
+    a.chunks_mut().next().unwrap().split(3);
+    core::mem::forget(a.chunks_mut());
+    assert_eq!(a.into_iter().count(), 1000);
+}
+
+#[test]
+fn bulk_load() {
+    let model: Vec<u64> = (0..100_000).collect();
+    let a: Anchor<u64> = Anchor::from_vec(model.clone());
+    assert_eq!(a.len(), model.len());
+    assert!(a.iter().eq(model.iter()));
+    // every chunk but the last one is full
+    let cap = a.chunks().next().unwrap().capacity();
+    assert_eq!(a.chunks().count(), (model.len() + cap - 1) / cap);
+    for i in (0..model.len()).step_by(997) {
+        assert_eq!(a[i], model[i]);
+    }
+
+    let mut b: Anchor<u64> = Anchor::new();
+    b.set_bulk_fill(0.5);
+    b.extend_from_slice(&model[..50_000]);
+    // no useful size hint
+    b.extend(model[50_000..].iter().filter(|_| true));
+    assert_eq!(b.len(), model.len());
+    assert!(b.iter().eq(model.iter()));
+    let chunks = b.chunks().count();
+    for chunk in b.chunks().take(chunks - 1) {
+        assert_eq!(chunk.len(), min_len(0.5, cap));
+    }
+    // there is space left for inserts
+    b.insert(3, 3);
+    assert_eq!(b.chunks().count(), chunks);
+
+    let c: Anchor<u64> = model.iter().copied().collect();
+    assert!(c.iter().eq(model.iter()));
+
+    // non-Copy elements are moved
+    let strings: Vec<String> = (0..1000).map(|i| i.to_string()).collect();
+    let d = Anchor::<String, 512>::from_vec(strings.clone());
+    assert!(d.iter().eq(strings.iter()));
+}
+
+#[test]
+fn split_off_append() {
+    let mut model: Vec<u64> = (0..100_000).collect();
+    let mut a: Anchor<u64, 512> = Anchor::from_vec(model.clone());
+    let cap = a.chunks().next().unwrap().capacity();
+    let chunks = a.chunks().count();
+
+    // in the middle of a chunk, at a chunk boundary, at the ends
+    for &at in &[50_000 + cap / 2, 20 * cap, model.len(), 0] {
+        let mut tail = a.split_off(at);
+        let model_tail = model.split_off(at);
+        assert_eq!(a.len(), model.len());
+        assert_eq!(tail.len(), model_tail.len());
+        assert!(a.iter().eq(model.iter()));
+        assert!(tail.iter().eq(model_tail.iter()));
+        for i in (0..model_tail.len()).step_by(cap / 3) {
+            assert_eq!(tail[i], model_tail[i]);
+        }
+        assert!(a.chunks().count() + tail.chunks().count() <= chunks + 1);
+
+        a.append(&mut tail);
+        model.extend(model_tail);
+        assert!(tail.is_empty());
+        assert_eq!(tail.chunks().count(), 0);
+        assert!(a.iter().eq(model.iter()));
+        for i in (0..model.len()).step_by(cap / 3) {
+            assert_eq!(a[i], model[i]);
+        }
+    }
+    // only splitting inside a chunk added one, its halves are full enough to be kept
+    assert_eq!(a.chunks().count(), chunks + 1);
+
+    // appending to an empty list takes over the chunks
+    let mut b: Anchor<u64, 512> = Anchor::new();
+    b.append(&mut a);
+    assert!(a.is_empty());
+    assert!(b.iter().eq(model.iter()));
+    assert_eq!(b.last(), model.last());
+}
+
+#[test]
+fn split_off_rebalance() {
+    fn check_fill(a: &Anchor<u64, 512>) {
+        let chunks: Vec<_> = a.chunks().collect();
+        let min = min_len(a.min_fill(), chunks[0].capacity());
+        for chunk in &chunks[..chunks.len() - 1] {
+            assert!(chunk.len() >= min);
+        }
+    }
+    let mut model: Vec<u64> = (0..10_000).collect();
+    let mut a: Anchor<u64, 512> = Anchor::new();
+    a.set_bulk_fill(0.5);
+    a.extend_from_slice(&model);
+    let half = a.chunks().next().unwrap().len();
+    let chunks = a.chunks().count();
+
+    // a single element left behind in self is merged into the chunk before it
+    let mut tail = a.split_off(10 * half + 1);
+    let mut model_tail = model.split_off(10 * half + 1);
+    assert_eq!(a.chunks().count(), 10);
+    check_fill(&a);
+    check_fill(&tail);
+
+    // a single element at the front of other is merged into the chunk behind it
+    let mut rest = tail.split_off(half - 1);
+    let model_rest = model_tail.split_off(half - 1);
+    assert_eq!(tail.chunks().count(), 1);
+    assert_eq!(rest.chunks().count(), chunks - 11);
+    check_fill(&rest);
+
+    // the shifted and re-offset indices keep working
+    rest.insert(3, 1);
+    assert_eq!(rest.remove(half), model_rest[half - 1]);
+    assert_eq!(rest.remove(3), 1);
+    tail.append(&mut rest);
+    a.append(&mut tail);
+    model.extend(model_tail);
+    model.extend(model_rest.iter().copied());
+    model.remove(model.len() - model_rest.len() + half - 1);
+    assert!(a.iter().eq(model.iter()));
+    for i in 0..model.len() {
+        assert_eq!(a[i], model[i]);
+    }
+    a.push(1);
+    assert_eq!(a[model.len()], 1);
+}
+
+#[test]
+fn drop_long() {
+    // lots of tiny chunks, dropping them recursively would overflow the stack
+    let mut a: Anchor<u8, 512> = Anchor::new();
+    let block = vec![1u8; 1 << 20];
+    for _ in 0..32 {
+        a.extend_from_slice(&block);
+    }
+    assert!(a.chunks().count() > 60_000);
+    drop(a);
+}
@@ -1,6 +1,10 @@
-use std::mem::MaybeUninit;
-use std::ops::Deref;
-use std::ops::DerefMut;
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+use core::num::NonZeroUsize;
+use core::ops::Deref;
+use core::ops::DerefMut;
 
 pub trait Link<T: ?Sized> {
     fn empty() -> Self
@@ -20,6 +24,7 @@ impl<T: ?Sized> Link<T> for usize {
     }
 }
 
+#[cfg(feature = "alloc")]
 impl<T: ?Sized> Link<T> for Option<Box<T>> {
     fn empty() -> Self {
         None
@@ -34,7 +39,7 @@ impl<T: ?Sized> Link<T> for *mut T {
     where
         T: Sized,
     {
-        std::ptr::null_mut()
+        core::ptr::null_mut()
     }
     fn is_empty(&self) -> bool {
         self.is_null()
@@ -48,6 +53,7 @@ pub trait LinkAdapter<T: ?Sized> {
 
 // what i _actually_ want to write is
 // for Option<Box>, i.e. a not fully specified type
+#[cfg(feature = "alloc")]
 impl<T: ?Sized> LinkAdapter<T> for Option<Box<()>> {
     type Link = Option<Box<T>>;
 }
@@ -60,16 +66,201 @@ impl<T: ?Sized> LinkAdapter<T> for usize {
     type Link = usize;
 }
 
+/// Names the Chunk<T> at a position in a slice of chunks, like the chunks of a Superblock.
+///
+/// Option<ChunkId<T>> is as big as a usize, None stands for the empty link usize::MAX.
+/// usize::MAX itself is not a valid position.
+#[repr(transparent)]
+pub struct ChunkId<T> {
+    /// the position, inverted so the empty link ends up as the niche
+    inverted: NonZeroUsize,
+    _type: PhantomData<fn() -> T>,
+}
+
+impl<T> ChunkId<T> {
+    /// panics if pos is the empty link.
+    pub fn new(pos: usize) -> Self {
+        Self::from_link(pos).expect("usize::MAX is the empty link, not a chunk")
+    }
+
+    /// the position of the chunk.
+    pub fn pos(self) -> usize {
+        !self.inverted.get()
+    }
+
+    /// the same chunk as a Chunk<U>, for when its contents change type,
+    /// e.g. after allocating it.
+    pub fn cast<U>(self) -> ChunkId<U> {
+        ChunkId {
+            inverted: self.inverted,
+            _type: PhantomData,
+        }
+    }
+
+    /// reads a usize link, None if it is empty.
+    pub fn from_link(link: usize) -> Option<Self> {
+        NonZeroUsize::new(!link).map(|inverted| Self {
+            inverted,
+            _type: PhantomData,
+        })
+    }
+
+    /// the usize link to id, or the empty link.
+    pub fn into_link(id: Option<Self>) -> usize {
+        match id {
+            Some(id) => id.pos(),
+            None => Link::<Self>::empty(),
+        }
+    }
+}
+
+// derive would require T to implement all of these too
+impl<T> Clone for ChunkId<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for ChunkId<T> {}
+
+impl<T> PartialEq for ChunkId<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.inverted == other.inverted
+    }
+}
+
+impl<T> Eq for ChunkId<T> {}
+
+impl<T> PartialOrd for ChunkId<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for ChunkId<T> {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        self.pos().cmp(&other.pos())
+    }
+}
+
+impl<T> core::hash::Hash for ChunkId<T> {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.pos().hash(state)
+    }
+}
+
+impl<T> core::fmt::Debug for ChunkId<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("ChunkId").field(&self.pos()).finish()
+    }
+}
+
 #[cfg(target_pointer_width = "64")]
-const PTR_SIZE: usize = 8;
+pub(crate) const PTR_SIZE: usize = 8;
 #[cfg(target_pointer_width = "32")]
-const PTR_SIZE: usize = 4;
+pub(crate) const PTR_SIZE: usize = 4;
 #[cfg(target_pointer_width = "16")]
-const PTR_SIZE: usize = 2;
+pub(crate) const PTR_SIZE: usize = 2;
+
+/// size of a chunk in bytes unless specified otherwise, a page on most platforms.
+pub const DEFAULT_CHUNK_SIZE: usize = 4096;
+
+/// stands in for the chunk size N when naming its layout, see ChunkSize.
+pub struct Size<const N: usize>;
+
+/// the layout of a chunk of a given size, implemented for Size<N> of every supported size:
+/// the powers of two from 512 bytes up to 2 MiB.
+///
+/// the buffer size can't be computed from a const parameter in a generic context (yet),
+/// so it is spelled out for every size instead.
+pub trait ChunkSize {
+    /// bytes in front of len and next_hint, the rest of the chunk
+    const BUF_SIZE: usize;
+    /// [u8; BUF_SIZE]
+    type Buf: Copy + AsRef<[u8]> + AsMut<[u8]>;
+    /// a zero-sized type aligned to the chunk size
+    type Align: Copy;
+    /// the smallest integer that can count the bytes in the buffer
+    type Len: ChunkLen;
+}
+
+/// the integer types a chunk can store its length in.
+pub trait ChunkLen: Copy + core::fmt::Debug {
+    fn to_usize(self) -> usize;
+    /// truncates, only call with lengths that fit.
+    fn from_usize(len: usize) -> Self;
+}
+
+impl ChunkLen for u16 {
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+    fn from_usize(len: usize) -> Self {
+        len as u16
+    }
+}
+
+impl ChunkLen for u32 {
+    fn to_usize(self) -> usize {
+        self as usize
+    }
+    fn from_usize(len: usize) -> Self {
+        len as u32
+    }
+}
+
+macro_rules! chunk_size {
+    ($size:literal, $align:ident, $len:ty) => {
+        #[doc(hidden)]
+        #[repr(align($size))]
+        #[derive(Clone, Copy)]
+        pub struct $align;
+
+        impl ChunkSize for Size<$size> {
+            const BUF_SIZE: usize = $size - core::mem::size_of::<$len>() - PTR_SIZE;
+            type Buf = [u8; $size - core::mem::size_of::<$len>() - PTR_SIZE];
+            type Align = $align;
+            type Len = $len;
+        }
+    };
+}
+
+chunk_size!(512, Align512, u16);
+chunk_size!(1024, Align1K, u16);
+chunk_size!(2048, Align2K, u16);
+chunk_size!(4096, Align4K, u16);
+chunk_size!(8192, Align8K, u16);
+chunk_size!(16384, Align16K, u16);
+chunk_size!(32768, Align32K, u16);
+chunk_size!(65536, Align64K, u16);
+chunk_size!(131072, Align128K, u32);
+chunk_size!(262144, Align256K, u32);
+chunk_size!(524288, Align512K, u32);
+chunk_size!(1048576, Align1M, u32);
+chunk_size!(2097152, Align2M, u32);
+
+/// Types a Chunk<u8> can be viewed as without copying, see Chunk::view.
+///
+/// safety: only implement this for types that
+/// * have no padding bytes
+/// * contain no pointers or references
+/// * don't need dropping, Copy already rules that out
+/// * are valid for any bit pattern
+///
+/// size and alignment are checked when viewing.
+pub unsafe trait ChunkPod: Copy + 'static {}
+
+macro_rules! chunk_pod {
+    ($($t:ty),*) => {
+        $(unsafe impl ChunkPod for $t {})*
+    };
+}
+
+chunk_pod!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
 
-const BUF_SIZE: usize = 4096 - 2 - PTR_SIZE;
+unsafe impl<T: ChunkPod, const N: usize> ChunkPod for [T; N] {}
 
-/// a single, page-sized chunk.
+/// a single chunk of SIZE bytes, a page by default.
 /// you can use this directly, or through a ChunkIndex
 /// if you need random access.
 ///
@@ -79,26 +270,33 @@ const BUF_SIZE: usize = 4096 - 2 - PTR_SIZE;
 /// you should put the chunks into a container
 /// which then actually owns them.
 #[derive(Clone)]
-#[repr(C, align(4096))]
-pub struct Chunk<T, L>
+#[repr(C)]
+pub struct Chunk<T, L, const SIZE: usize = DEFAULT_CHUNK_SIZE>
 where
     L: LinkAdapter<Self>,
+    Size<SIZE>: ChunkSize,
 {
     _zst: [T; 0],
+    /// every chunk starts at a multiple of SIZE
+    _align: [<Size<SIZE> as ChunkSize>::Align; 0],
     /// where the user data is actually stored
-    /// 4096 - 2 - 8
-    buf: [u8; BUF_SIZE],
-    len: u16,
+    /// SIZE - size of len - 8
+    buf: <Size<SIZE> as ChunkSize>::Buf,
+    len: <Size<SIZE> as ChunkSize>::Len,
     /// this is a pointer-sized hint on what the next chunk may be
     /// depending on usage this may be a pointer
     /// or an offset for example
     pub(crate) next_hint: L::Link,
 }
 
-impl<T, L> Chunk<T, L>
+impl<T, L, const SIZE: usize> Chunk<T, L, SIZE>
 where
     L: LinkAdapter<Self>,
+    Size<SIZE>: ChunkSize,
 {
+    /// bytes available for elements
+    pub(crate) const BUF_SIZE: usize = <Size<SIZE> as ChunkSize>::BUF_SIZE;
+
     /// Pass in an uninitialized chunk of memory
     /// get out a Chunk
     #[inline]
@@ -111,19 +309,29 @@ where
     }
 
     /// only call with valid pointers
-    pub unsafe fn len_ptr(s: *mut Self) -> *mut u16 {
+    pub unsafe fn len_ptr(s: *mut Self) -> *mut <Size<SIZE> as ChunkSize>::Len {
         let s = s as *mut u8;
-        let s = s.add(BUF_SIZE);
+        let s = s.add(Self::BUF_SIZE);
         s as _
     }
 
     /// only call with valid pointers
     pub unsafe fn next_hint(s: *mut Self) -> *mut L::Link {
         let s = s as *mut u8;
-        let s = s.add(BUF_SIZE + 2);
+        let s = s.add(Self::BUF_SIZE + core::mem::size_of::<<Size<SIZE> as ChunkSize>::Len>());
         s as _
     }
 
+    /// allocates a chunk on the heap and initializes it there,
+    /// big chunks would not fit on the stack.
+    #[cfg(feature = "alloc")]
+    pub fn boxed() -> Box<Self> {
+        let mut store = Box::new_uninit();
+        Self::initialize(&mut store);
+        // initialize fully initializes the store
+        unsafe { store.assume_init() }
+    }
+
     /// After a call to initialize the whole struct ist guaranteed to be initialized.
     /// If the passed struct was partially initialized before, drops will not be called.
     pub fn initialize(store: &mut MaybeUninit<Self>) -> &mut Self {
@@ -131,11 +339,11 @@ where
         // so runtime-checks have to do
         // they should be evaluated at compile time anyway
         // so at least probably no runtime cost
-        assert!(std::mem::size_of::<T>() <= BUF_SIZE);
-        assert!(std::mem::align_of::<T>() <= 4096);
+        assert!(core::mem::size_of::<T>() <= Self::BUF_SIZE);
+        assert!(core::mem::align_of::<T>() <= SIZE);
 
-        assert_eq!(std::mem::size_of::<L::Link>(), 8);
-        assert_eq!(std::mem::align_of::<L::Link>(), 8);
+        assert_eq!(core::mem::size_of::<L::Link>(), 8);
+        assert_eq!(core::mem::align_of::<L::Link>(), 8);
 
         // 1) get the offsets
         let store_ptr = store.as_mut_ptr() as *mut MaybeUninit<u8>;
@@ -143,25 +351,26 @@ where
 
         // offset to "len" field
         // this is safe because its within the allocation
-        let len_ptr = unsafe { store_ptr.add(BUF_SIZE) };
+        let len_ptr = unsafe { store_ptr.add(Self::BUF_SIZE) };
 
         // offset to "next" field
         // again, safe because inside the same allocation
-        let next_ptr = unsafe { len_ptr.add(2) };
+        let next_ptr =
+            unsafe { len_ptr.add(core::mem::size_of::<<Size<SIZE> as ChunkSize>::Len>()) };
 
         // 2) turn into the right pointer types
         let buf_ptr = buf_ptr as *mut u8;
-        let len_ptr = len_ptr as *mut u16;
+        let len_ptr = len_ptr as *mut <Size<SIZE> as ChunkSize>::Len;
         let next_ptr = next_ptr as *mut L::Link;
 
         // 3) initialize
         unsafe {
-            for o in 0..BUF_SIZE {
+            for o in 0..Self::BUF_SIZE {
                 buf_ptr.add(o).write(0);
             }
         }
         // the alignment must always work out because we don't allow for pointer sizes < 16
-        unsafe { len_ptr.write(0u16) };
+        unsafe { len_ptr.write(ChunkLen::from_usize(0)) };
         unsafe { next_ptr.write(L::Link::empty()) };
 
         // buf has been zero-initialized
@@ -173,15 +382,19 @@ where
         unsafe { store.get_mut() }
     }
 
+    fn set_len(&mut self, len: usize) {
+        self.len = ChunkLen::from_usize(len);
+    }
+
     /// pushes a value, unless the list is full
     pub fn push(&mut self, value: T) -> Option<T> {
-        let len = self.len as usize;
+        let len = self.len();
         let values = self.as_uninit_slice_mut();
 
         if let Some(place) = values.get_mut(len) {
             place.write(value);
             // increment len, now that the element is written
-            self.len += 1;
+            self.set_len(len + 1);
             None
         } else {
             Some(value)
@@ -190,22 +403,19 @@ where
 
     /// pops the last value
     pub fn pop(&mut self) -> Option<T> {
-        if self.len == 0 {
+        if self.len() == 0 {
             return None;
         };
 
-        self.len -= 1;
-        let last = self.len as usize;
-
-        let values = self.as_uninit_slice_mut();
+        let last = self.len() - 1;
+        self.set_len(last);
 
-        let mut value = MaybeUninit::uninit();
-        std::mem::swap(&mut value, &mut values[last]);
+        let values = self.as_uninit_slice();
 
         // this is safe because it contains the (initialized)
-        // value from the list, we just swapped it out.
-        // the list now contains the uninitialized value.
-        let value = unsafe { value.assume_init() };
+        // value from the list, which is no longer part of it.
+        // the bytes stay behind, so the buffer stays initialized, see view.
+        let value = unsafe { values[last].as_ptr().read() };
         Some(value)
     }
 
@@ -216,14 +426,14 @@ where
 
     /// number of elements in this chunk
     pub fn len(&self) -> usize {
-        self.len as usize
+        self.len.to_usize()
     }
 
     /// inserts element at index, shifting all following elements up by one.
     /// if there is not enough space in this chunk the element is returned
     /// also returns the element if the index is out of bounds
     pub fn insert(&mut self, index: usize, element: T) -> Result<&mut T, T> {
-        let len = self.len as usize;
+        let len = self.len();
         let index_in_bounds = index <= len;
         let has_space = len < self.capacity();
         if index_in_bounds && has_space {
@@ -238,12 +448,12 @@ where
 
             // this is safe: we just checked the capacity is enough to fit one more
             // element, we are just shifting everything up by one
-            unsafe { std::ptr::copy(insert_index, copy_target, remainder) }
+            unsafe { core::ptr::copy(insert_index, copy_target, remainder) }
 
             // we made space at the index, time to put in the new element
             values[index].write(element);
 
-            self.len += 1;
+            self.set_len(len + 1);
 
             let values = self.as_uninit_slice_mut();
             let v = &mut values[index] as *mut MaybeUninit<T> as *mut T;
@@ -258,16 +468,14 @@ where
     /// removes and returns element at indxe
     /// if index is out of bounds, returns None
     pub fn remove(&mut self, index: usize) -> Option<T> {
-        let len = self.len() as usize;
+        let len = self.len();
         if index < len {
-            let mut val = MaybeUninit::uninit();
             let values = self.as_uninit_slice_mut();
 
-            std::mem::swap(&mut val, &mut values[index]);
             // we checked that index is < len, so values[index] is initialized
-            // we swapped the initialized value out into val
-            // so now val is initialized and values[index] is not.
-            let val = unsafe { val.assume_init() };
+            // it is overwritten below, the last slot keeps a stale copy of its bytes,
+            // so the buffer stays initialized, see view.
+            let val = unsafe { values[index].as_ptr().read() };
 
             // time to fix up the values
             let copy_target = &mut values[index] as *mut MaybeUninit<T>;
@@ -279,9 +487,9 @@ where
             let remainder = len - (index + 1);
 
             // this is safe, we stay within bounds and are just shrinking
-            unsafe { std::ptr::copy(copy_source, copy_target, remainder) };
+            unsafe { core::ptr::copy(copy_source, copy_target, remainder) };
 
-            self.len -= 1;
+            self.set_len(len - 1);
 
             Some(val)
         } else {
@@ -289,18 +497,81 @@ where
         }
     }
 
+    /// moves all elements of other to the end of self, leaving other empty.
+    /// if there is not enough space left in self nothing is moved and false is returned.
+    ///
+    /// just like split this does not touch the next_hints.
+    pub fn append(&mut self, other: &mut Self) -> bool {
+        self.take_front(other, other.len())
+    }
+
+    /// moves the first count elements of other to the end of self.
+    /// the remaining elements of other are shifted forward.
+    /// if there is not enough space left in self, or other has less than count elements,
+    /// nothing is moved and false is returned.
+    ///
+    /// just like split this does not touch the next_hints.
+    pub fn take_front(&mut self, other: &mut Self, count: usize) -> bool {
+        let len = self.len();
+        let other_len = other.len();
+        if len + count > self.capacity() || count > other_len {
+            return false;
+        }
+        let source = other.as_uninit_slice_mut().as_mut_ptr();
+        let target = self.as_uninit_slice_mut()[len..].as_mut_ptr();
+        // this is ok, we just checked that there is enough space and elements
+        // and self and other can't be the same chunk.
+        unsafe { source.copy_to_nonoverlapping(target, count) };
+        // close the gap in other
+        unsafe { core::ptr::copy(source.add(count), source, other_len - count) };
+        self.set_len(len + count);
+        other.set_len(other_len - count);
+        true
+    }
+
+    /// copies as many elements of values to the end of self as fit.
+    /// returns how many that were.
+    pub fn extend_from_slice(&mut self, values: &[T]) -> usize
+    where
+        T: Copy,
+    {
+        let count = values.len().min(self.capacity() - self.len());
+        // T is Copy, so values can keep using them
+        unsafe { self.extend_from_raw(values.as_ptr(), count) };
+        count
+    }
+
+    /// moves count elements from values to the end of self.
+    ///
+    /// unsafety: values needs to point to count initialized elements,
+    /// which are not used again unless T is Copy.
+    ///
+    /// panics if they don't fit.
+    pub unsafe fn extend_from_raw(&mut self, values: *const T, count: usize) {
+        let len = self.len();
+        assert!(
+            count <= self.capacity() - len,
+            "{} elements don't fit into a chunk with {} spaces left",
+            count,
+            self.capacity() - len
+        );
+        let target = self.as_uninit_slice_mut()[len..].as_mut_ptr() as *mut T;
+        values.copy_to_nonoverlapping(target, count);
+        self.set_len(len + count);
+    }
+
     pub fn as_uninit_slice(&self) -> &[MaybeUninit<T>] {
         // this is "safe" because we only transmute it to MaybeUninit
         // i.e. not actually doing anything.
         // u8 does not have drop.
-        let (_pre, values, _post) = unsafe { self.buf.align_to() };
+        let (_pre, values, _post) = unsafe { self.buf.as_ref().align_to() };
         values
     }
     pub fn as_uninit_slice_mut(&mut self) -> &mut [MaybeUninit<T>] {
         // this is "safe" because we only transmute it to MaybeUninit
         // i.e. not actually doing anything.
         // u8 does not have drop.
-        let (_pre, values, _post) = unsafe { self.buf.align_to_mut() };
+        let (_pre, values, _post) = unsafe { self.buf.as_mut().align_to_mut() };
         values
     }
 
@@ -317,7 +588,7 @@ where
     /// You will probably need to append something like:
     ///
     /// ```ignore
-    /// std::mem::swap(&mut self.next_hint, &mut other.next_hint);
+    /// core::mem::swap(&mut self.next_hint, &mut other.next_hint);
     /// // owned
     /// self.next_hint = other;
     /// //
@@ -340,8 +611,8 @@ where
             let target = target.as_mut_ptr();
             // this is ok, we checked the lengths and everything
             unsafe { source.copy_to_nonoverlapping(target, len) };
-            self.len -= len as u16;
-            other.len = len as u16;
+            self.set_len(index);
+            other.set_len(len);
         }
         // notice how the next_hint is not modified
 
@@ -358,27 +629,39 @@ where
 }
 
 // todo: use these in downstream implementations
-impl<T, L> Chunk<T, L>
+impl<T, L, const SIZE: usize> Chunk<T, L, SIZE>
 where
     L: LinkAdapter<Self, Link = usize>,
+    Size<SIZE>: ChunkSize,
 {
     //TODO: maybe take (&mut[], self_index, other_index, split_at) instead
     pub fn split_usize<'a>(
         &mut self,
         index: usize,
         other: &'a mut MaybeUninit<Self>,
-        other_id: usize,
+        other_id: ChunkId<T>,
     ) -> &'a mut Self {
         let other = self.split(index, other);
         other.next_hint = self.next_hint;
-        self.next_hint = other_id;
+        self.set_next(Some(other_id));
         other
     }
+
+    /// the chunk next_hint links to, if any.
+    pub fn next(&self) -> Option<ChunkId<T>> {
+        ChunkId::from_link(self.next_hint)
+    }
+
+    pub fn set_next(&mut self, next: Option<ChunkId<T>>) {
+        self.next_hint = ChunkId::into_link(next);
+    }
 }
 
-impl<T, L> Chunk<T, L>
+#[cfg(feature = "alloc")]
+impl<T, L, const SIZE: usize> Chunk<T, L, SIZE>
 where
     L: LinkAdapter<Self, Link = Option<Box<Self>>>,
+    Size<SIZE>: ChunkSize,
 {
     pub fn split_box<'a>(&mut self, index: usize, mut other: Box<MaybeUninit<Self>>) {
         let other_mut = self.split(index, &mut other);
@@ -389,9 +672,10 @@ where
     }
 }
 
-impl<T, L> Chunk<T, L>
+impl<T, L, const SIZE: usize> Chunk<T, L, SIZE>
 where
     L: LinkAdapter<Self, Link = *mut Self>,
+    Size<SIZE>: ChunkSize,
 {
     pub fn split_mut<'a>(
         &mut self,
@@ -406,9 +690,52 @@ where
     }
 }
 
-impl<T, L> Drop for Chunk<T, L>
+impl<const SIZE: usize> Chunk<u8, usize, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    /// panics unless a Chunk<T> has the same layout as this one.
+    pub(crate) fn check_pod<T: ChunkPod>() {
+        // evaluated at compile time, like the checks in initialize
+        assert!(core::mem::size_of::<T>() != 0);
+        assert!(core::mem::size_of::<T>() <= Self::BUF_SIZE);
+        assert!(core::mem::align_of::<T>() <= SIZE);
+    }
+
+    /// reinterprets the bytes of this chunk as a Chunk<T>, without copying.
+    /// len is taken as is, so it counts elements of T.
+    /// returns None if len is more than a Chunk<T> can hold.
+    ///
+    /// every byte of a Chunk<u8> is initialized: initialize zeroes the buffer,
+    /// removing elements leaves their bytes behind and T has no padding.
+    /// so this never reads uninitialized memory.
+    ///
+    /// panics if T is zero sized, does not fit into the chunk or needs more alignment.
+    pub fn view<T: ChunkPod>(&self) -> Option<&Chunk<T, usize, SIZE>> {
+        Self::check_pod::<T>();
+        if self.len() > Self::BUF_SIZE / core::mem::size_of::<T>() {
+            return None;
+        }
+        // same layout, any bit pattern is a valid T, and len fits
+        Some(unsafe { &*(self as *const Self as *const Chunk<T, usize, SIZE>) })
+    }
+
+    /// like view, but writable.
+    /// writing a T leaves no uninitialized bytes behind, as it has no padding.
+    pub fn view_mut<T: ChunkPod>(&mut self) -> Option<&mut Chunk<T, usize, SIZE>> {
+        Self::check_pod::<T>();
+        if self.len() > Self::BUF_SIZE / core::mem::size_of::<T>() {
+            return None;
+        }
+        // same as view
+        Some(unsafe { &mut *(self as *mut Self as *mut Chunk<T, usize, SIZE>) })
+    }
+}
+
+impl<T, L, const SIZE: usize> Drop for Chunk<T, L, SIZE>
 where
     L: LinkAdapter<Self>,
+    Size<SIZE>: ChunkSize,
     // i would like to only implement drop for types that actually need dropping
     // but rust won't let me. could work around that with a wrapper type.
     //T:Drop,
@@ -420,36 +747,40 @@ where
     // will not drop next!
 }
 
-impl<T, L> Deref for Chunk<T, L>
+impl<T, L, const SIZE: usize> Deref for Chunk<T, L, SIZE>
 where
     L: LinkAdapter<Self>,
+    Size<SIZE>: ChunkSize,
 {
     type Target = [T];
     fn deref(&self) -> &Self::Target {
         let base = &self.buf as *const _ as *const T;
 
         // safe because self.len is guaranteed to actually represent the initialized len.
-        unsafe { std::slice::from_raw_parts(base, self.len as usize) }
+        unsafe { core::slice::from_raw_parts(base, self.len()) }
     }
 }
 
-impl<T, L> DerefMut for Chunk<T, L>
+impl<T, L, const SIZE: usize> DerefMut for Chunk<T, L, SIZE>
 where
     L: LinkAdapter<Self>,
+    Size<SIZE>: ChunkSize,
 {
     fn deref_mut(&mut self) -> &mut Self::Target {
         let base = &mut self.buf as *mut _ as *mut T;
 
         // safe because self.len is guaranteed to actually represent the initialized len.
-        unsafe { std::slice::from_raw_parts_mut(base, self.len as usize) }
+        let len = self.len();
+        unsafe { core::slice::from_raw_parts_mut(base, len) }
     }
 }
 
-impl<T: std::fmt::Debug, L> std::fmt::Debug for Chunk<T, L>
+impl<T: core::fmt::Debug, L, const SIZE: usize> core::fmt::Debug for Chunk<T, L, SIZE>
 where
     L: LinkAdapter<Self>,
+    Size<SIZE>: ChunkSize,
 {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         let slice: &[T] = self;
         f.debug_list().entries(slice).finish()
     }
@@ -458,6 +789,26 @@ where
 #[test]
 fn sizes() {
     assert_eq!(std::mem::size_of::<Chunk<u8, usize>>(), 4096);
+    assert_eq!(std::mem::size_of::<Chunk<u8, usize, 512>>(), 512);
+    assert_eq!(std::mem::align_of::<Chunk<u8, usize, 512>>(), 512);
+    assert_eq!(std::mem::size_of::<Chunk<u8, usize, 65536>>(), 65536);
+    assert_eq!(std::mem::size_of::<Chunk<u8, usize, 2097152>>(), 2097152);
+    assert_eq!(
+        std::mem::size_of::<Option<ChunkId<u8>>>(),
+        std::mem::size_of::<usize>()
+    );
+    assert_eq!(ChunkId::<u8>::from_link(usize::MAX), None);
+    assert_eq!(ChunkId::into_link(Some(ChunkId::<u8>::new(3))), 3);
+
+    // a 2 MiB chunk holds more bytes than a u16 can count
+    let mut store = Box::<Chunk<u8, usize, 2097152>>::new_uninit();
+    let chunk = Chunk::initialize(&mut store);
+    while chunk.push(1).is_none() {}
+    assert_eq!(chunk.len(), 2097152 - 4 - PTR_SIZE);
+    assert_eq!(
+        chunk.iter().map(|b| *b as usize).sum::<usize>(),
+        chunk.len()
+    );
 }
 
 #[test]
@@ -465,7 +816,10 @@ fn push_pop() {
     let store = Box::new(MaybeUninit::uninit());
 
     let mut chunk = Chunk::<_, usize>::new(*store);
-    assert_eq!(chunk.capacity(), BUF_SIZE / std::mem::size_of::<usize>());
+    assert_eq!(
+        chunk.capacity(),
+        Chunk::<usize, usize>::BUF_SIZE / std::mem::size_of::<usize>()
+    );
 
     for i in 0usize..chunk.capacity() {
         assert_eq!(chunk.push(i), None);
@@ -542,6 +896,36 @@ fn split() {
     assert_eq!(chunk.len(), 0);
 }
 
+#[test]
+fn append() {
+    let store = Box::new(MaybeUninit::uninit());
+    let mut chunk: Chunk<u128, usize> = Chunk::new(*store);
+    let store = Box::new(MaybeUninit::uninit());
+    let mut other: Chunk<u128, usize> = Chunk::new(*store);
+    let capacity = chunk.capacity();
+
+    for i in 0..10 {
+        chunk.push(i);
+        other.push(i + 10);
+    }
+    assert!(chunk.append(&mut other));
+    assert_eq!(other.len(), 0);
+    assert_eq!(&chunk[..], &(0..20).collect::<Vec<_>>()[..]);
+
+    for i in 0..10 {
+        other.push(i + 20);
+    }
+    assert!(!chunk.take_front(&mut other, 11));
+    assert!(chunk.take_front(&mut other, 4));
+    assert_eq!(&chunk[..], &(0..24).collect::<Vec<_>>()[..]);
+    assert_eq!(&other[..], &(24..30).collect::<Vec<_>>()[..]);
+
+    while other.push(0).is_none() {}
+    assert!(!chunk.append(&mut other));
+    assert_eq!(chunk.len(), 24);
+    assert_eq!(other.len(), capacity);
+}
+
 #[test]
 #[should_panic]
 fn split_oob() {
@@ -553,3 +937,39 @@ fn split_oob() {
     // split initializes, so we need to drop
     let _new = unsafe { store.assume_init() };
 }
+
+#[test]
+fn views() {
+    let store = Box::new(MaybeUninit::uninit());
+    let mut chunk: Chunk<u8, usize> = Chunk::new(*store);
+    {
+        let words = chunk.view_mut::<u32>().unwrap();
+        words.push(0x0403_0201);
+        words.push(u32::MAX);
+    }
+    // len counts elements, the second u32 is not part of a byte view
+    assert_eq!(&chunk[..], &[1, 2]);
+    assert_eq!(
+        &chunk.view::<[u8; 4]>().unwrap()[..],
+        &[[1, 2, 3, 4], [0xff; 4]]
+    );
+    assert_eq!(
+        &chunk.view::<u64>().unwrap()[..],
+        &[0xffff_ffff_0403_0201, 0]
+    );
+
+    // popped bytes are still there, and still initialized
+    let mut bytes: Chunk<u8, usize> = Chunk::new(*Box::new(MaybeUninit::uninit()));
+    for b in 1..=8 {
+        bytes.push(b);
+    }
+    bytes.pop();
+    bytes.remove(0);
+    assert_eq!(bytes.view::<u64>().unwrap()[0], 0x0807_0706_0504_0302);
+
+    // more elements than a Chunk<u128> can hold
+    while chunk.push(0).is_none() {}
+    assert!(chunk.view::<u128>().is_none());
+    assert!(chunk.view_mut::<u16>().is_none());
+    assert_eq!(chunk.view::<i8>().unwrap().len(), chunk.len());
+}
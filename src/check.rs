@@ -0,0 +1,383 @@
+//! Consistency checks for a Superblock, see Superblock::check.
+//!
+//! Every lock table entry with a non-empty root is taken as the start of a list.
+//! The checks only look at how chunks are linked and which ones are free,
+//! not at the contents of the lists.
+use crate::base_chunk::ChunkId;
+use crate::base_chunk::ChunkSize;
+use crate::base_chunk::Link;
+use crate::base_chunk::Size;
+use crate::base_chunk::DEFAULT_CHUNK_SIZE;
+use crate::freelist::Entry;
+use crate::superblock::Superblock;
+use crate::superblock::HEADER_CHUNK;
+type Chunk<T, const SIZE: usize = DEFAULT_CHUNK_SIZE> = crate::base_chunk::Chunk<T, usize, SIZE>;
+
+/// who a chunk belongs to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Owner {
+    /// the header, lock table, log or checksum table
+    Reserved,
+    /// the list rooted in this lock table entry
+    List(usize),
+}
+
+/// A single broken invariant, found by Superblock::check.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// a chunk of list links to a chunk outside of the superblock,
+    /// from is None if the root itself is out of bounds.
+    OutOfBounds {
+        list: usize,
+        from: Option<usize>,
+        to: usize,
+    },
+    /// chunk of list links back to to, which comes earlier in the same list.
+    Cycle { list: usize, from: usize, to: usize },
+    /// chunk of list does not match its checksum, its link is not followed.
+    Corrupt { list: usize, chunk: usize },
+    /// chunk is reachable from two owners.
+    /// the rest of second is not checked, it would all be shared as well.
+    Shared {
+        chunk: usize,
+        first: Owner,
+        second: Owner,
+    },
+    /// chunk belongs to owner, but is also part of a free extent.
+    FreeAndUsed { chunk: usize, owner: Owner },
+    /// chunk is neither free nor part of any list, nobody is ever going to use it again.
+    Leaked { chunk: usize },
+    /// the freelist chunk claims to hold more entries than fit into it.
+    /// its entries are not checked.
+    BadLength { chunk: usize, len: usize },
+    /// the free extent (start, len) reaches past the end of the superblock.
+    EntryOutOfBounds { start: u32, len: u32 },
+    /// the free extent (start, len) starts before the previous one.
+    Unsorted { start: u32, len: u32 },
+    /// the free extent (start, len) overlaps with the previous one.
+    Overlap { start: u32, len: u32 },
+    /// the free extent (start, len) starts right where the previous one ends,
+    /// they should have been merged.
+    Adjacent { start: u32, len: u32 },
+}
+
+/// The result of Superblock::check, lists every violation that was found.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub violations: Vec<Violation>,
+    /// number of lists that were checked, including the freelist
+    pub lists: usize,
+    /// number of chunks that belong to a list
+    pub used: usize,
+    /// number of chunks in free extents
+    pub free: usize,
+}
+
+impl Report {
+    /// true if no violations were found
+    pub fn is_ok(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+/// see Superblock::check.
+pub(crate) fn check<const SIZE: usize>(superblock: &Superblock<SIZE>) -> Report
+where
+    Size<SIZE>: ChunkSize,
+{
+    let header = *superblock.header();
+    let n_chunks = superblock.len();
+    let mut report = Report::default();
+    let mut owners: Vec<Option<Owner>> = vec![None; n_chunks];
+
+    let log = header.log as usize..(header.log + header.log_slots + 1) as usize;
+    let bootstrap = [HEADER_CHUNK, header.lock_table as usize];
+    let reserved = bootstrap
+        .iter()
+        .copied()
+        .chain(log)
+        .chain(superblock.checksum_table.clone());
+    for chunk in reserved {
+        owners[chunk] = Some(Owner::Reserved);
+    }
+
+    let freelist = header.freelist as usize;
+    let mut freelist_chunks = Vec::new();
+    for list in 0..header.lock_entries as usize {
+        // the caller has exclusive access, nobody is holding the entry
+        let root = unsafe { superblock.lock_table().as_ref().unwrap()[list].1 .0 };
+        if Link::<Chunk<u8, SIZE>>::is_empty(&root) {
+            continue;
+        }
+        report.lists += 1;
+        let chunks = walk(superblock, list, root, &mut owners, &mut report);
+        if list == freelist {
+            freelist_chunks = chunks;
+        }
+    }
+    report.used = owners.iter().filter(|o| o.is_some()).count();
+
+    let mut free = vec![false; n_chunks];
+    let mut previous: Option<Entry> = None;
+    for id in freelist_chunks {
+        // the chunk is part of the freelist, nobody is writing to it.
+        // any bit pattern is a valid entry, view only needs to check the length.
+        let bytes = unsafe { superblock.get::<u8>(ChunkId::new(id)).get_ref() };
+        let chunk = match bytes.view::<Entry>() {
+            Some(chunk) => chunk,
+            None => {
+                report.violations.push(Violation::BadLength {
+                    chunk: id,
+                    len: bytes.len(),
+                });
+                continue;
+            }
+        };
+        for e in chunk.iter() {
+            check_entry(*e, previous, &owners, &mut free, &mut report);
+            previous = Some(*e);
+        }
+    }
+
+    let leaked = (0..n_chunks)
+        .filter(|chunk| owners[*chunk].is_none() && !free[*chunk])
+        .map(|chunk| Violation::Leaked { chunk });
+    report.violations.extend(leaked);
+    report
+}
+
+/// follows the list starting at root, marking every chunk as belonging to it.
+/// stops at the first broken link, returns the chunks up to there.
+fn walk<const SIZE: usize>(
+    superblock: &Superblock<SIZE>,
+    list: usize,
+    root: usize,
+    owners: &mut [Option<Owner>],
+    report: &mut Report,
+) -> Vec<usize>
+where
+    Size<SIZE>: ChunkSize,
+{
+    let mut chunks = Vec::new();
+    let mut from = None;
+    let mut current = root;
+    while !Link::<Chunk<u8, SIZE>>::is_empty(&current) {
+        let violation = match owners.get(current) {
+            None => Some(Violation::OutOfBounds {
+                list,
+                from,
+                to: current,
+            }),
+            Some(Some(Owner::List(l))) if *l == list => Some(Violation::Cycle {
+                list,
+                from: from.unwrap(),
+                to: current,
+            }),
+            Some(Some(first)) => Some(Violation::Shared {
+                chunk: current,
+                first: *first,
+                second: Owner::List(list),
+            }),
+            Some(None) => None,
+        };
+        if let Some(v) = violation {
+            report.violations.push(v);
+            break;
+        }
+        owners[current] = Some(Owner::List(list));
+        chunks.push(current);
+        from = Some(current);
+        // the caller has exclusive access, nobody is writing to the chunk
+        let chunk = match unsafe { superblock.load::<u8>(ChunkId::new(current)) } {
+            Ok(chunk) => unsafe { chunk.get_ref() },
+            Err(_) => {
+                report.violations.push(Violation::Corrupt {
+                    list,
+                    chunk: current,
+                });
+                break;
+            }
+        };
+        // next_hint is in the same spot for every kind of chunk
+        current = chunk.next_hint;
+    }
+    chunks
+}
+
+/// checks the free extent e, marking its chunks in free.
+fn check_entry(
+    e: Entry,
+    previous: Option<Entry>,
+    owners: &[Option<Owner>],
+    free: &mut [bool],
+    report: &mut Report,
+) {
+    let (start, len) = (e.start, e.len);
+    let end = start as usize + len as usize;
+    if end > owners.len() {
+        report
+            .violations
+            .push(Violation::EntryOutOfBounds { start, len });
+    }
+    if let Some(p) = previous {
+        let p_end = p.start as usize + p.len as usize;
+        let violation = if start < p.start {
+            Some(Violation::Unsorted { start, len })
+        } else if (start as usize) < p_end {
+            Some(Violation::Overlap { start, len })
+        } else if start as usize == p_end {
+            Some(Violation::Adjacent { start, len })
+        } else {
+            None
+        };
+        report.violations.extend(violation);
+    }
+    for chunk in start as usize..end.min(owners.len()) {
+        report.free += 1;
+        free[chunk] = true;
+        if let Some(owner) = owners[chunk] {
+            report
+                .violations
+                .push(Violation::FreeAndUsed { chunk, owner });
+        }
+    }
+}
+
+#[test]
+fn violations() {
+    let mut s: Superblock = Superblock::anonymous(64).unwrap();
+    let freelist = s.header().freelist as usize;
+
+    // a list of two chunks in entry 1
+    let ids: Vec<ChunkId<u64>> = (0..2)
+        .map(|_| s.alloc_chunks(freelist, 1).unwrap().unwrap())
+        .collect();
+    Chunk::initialize(unsafe { s.get_mut(ids[0]) }).set_next(Some(ids[1]));
+    Chunk::initialize(unsafe { s.get_mut(ids[1]) });
+    s.write(1).set_start(Some(ids[0]));
+
+    let report = s.check();
+    assert!(report.is_ok(), "{:?}", report);
+    assert_eq!(report.lists, 2);
+    assert_eq!(report.used + report.free, s.len());
+
+    // entry 2 shares the second chunk, which links back to the first
+    s.write(2).set_start(Some(ids[1]));
+    unsafe { s.get_mut(ids[1]).get_mut() }.set_next(Some(ids[0]));
+    // entry 3 is out of bounds
+    s.write(3).0 = 1000;
+    let report = s.check();
+    assert_eq!(
+        report.violations,
+        vec![
+            Violation::Cycle {
+                list: 1,
+                from: ids[1].pos(),
+                to: ids[0].pos()
+            },
+            Violation::Shared {
+                chunk: ids[1].pos(),
+                first: Owner::List(1),
+                second: Owner::List(2)
+            },
+            Violation::OutOfBounds {
+                list: 3,
+                from: None,
+                to: 1000
+            },
+        ]
+    );
+    s.write(2).0 = usize::MAX;
+    s.write(3).0 = usize::MAX;
+    unsafe { s.get_mut(ids[1]).get_mut() }.set_next(None);
+
+    // the freelist claims the first chunk of the list, and an extent that touches the next one
+    let root = s.read(freelist).start().unwrap();
+    let chunk = unsafe { s.get_mut::<Entry>(root).get_mut() };
+    let last = chunk[chunk.len() - 1];
+    let used = Entry {
+        start: ids[0].pos() as u32,
+        len: 1,
+    };
+    chunk.insert(0, used).unwrap();
+    chunk.push(Entry {
+        start: last.start + last.len,
+        len: 1,
+    });
+    let report = s.check();
+    assert_eq!(
+        report.violations,
+        vec![
+            Violation::FreeAndUsed {
+                chunk: ids[0].pos(),
+                owner: Owner::List(1)
+            },
+            Violation::Adjacent {
+                start: last.start + last.len,
+                len: 1
+            },
+            Violation::FreeAndUsed {
+                chunk: (last.start + last.len) as usize,
+                owner: Owner::Reserved
+            },
+        ]
+    );
+}
+
+#[test]
+fn leaked() {
+    // not the default chunk size
+    let mut s = Superblock::<8192>::anonymous(32).unwrap();
+    let freelist = s.header().freelist as usize;
+    let report = s.check();
+    assert!(report.is_ok(), "{:?}", report);
+    assert_eq!(report.used + report.free, s.len());
+
+    // allocated, but never linked into a list
+    let id: ChunkId<u64> = s.alloc_chunks(freelist, 1).unwrap().unwrap();
+    let report = s.check();
+    assert_eq!(
+        report.violations,
+        vec![Violation::Leaked { chunk: id.pos() }]
+    );
+}
+
+#[test]
+fn self_cycle() {
+    let mut s: Superblock = Superblock::anonymous(32).unwrap();
+    let freelist = s.header().freelist as usize;
+    let id: ChunkId<u64> = s.alloc_chunks(freelist, 1).unwrap().unwrap();
+    Chunk::initialize(unsafe { s.get_mut(id) }).set_next(Some(id));
+    s.write(1).set_start(Some(id));
+    let report = s.check();
+    assert_eq!(
+        report.violations,
+        vec![Violation::Cycle {
+            list: 1,
+            from: id.pos(),
+            to: id.pos()
+        }]
+    );
+}
+
+#[test]
+fn free_and_used() {
+    let mut s: Superblock = Superblock::anonymous(32).unwrap();
+    let freelist = s.header().freelist as usize;
+    let id: ChunkId<u64> = s.alloc_chunks(freelist, 1).unwrap().unwrap();
+    Chunk::initialize(unsafe { s.get_mut(id) });
+    s.write(1).set_start(Some(id));
+    assert!(s.check().is_ok());
+
+    // freed while still being part of the list
+    unsafe { s.free_chunks(freelist, id, 1) }.unwrap();
+    let report = s.check();
+    assert_eq!(
+        report.violations,
+        vec![Violation::FreeAndUsed {
+            chunk: id.pos(),
+            owner: Owner::List(1)
+        }]
+    );
+    assert_eq!(report.used + report.free, s.len() + 1);
+}
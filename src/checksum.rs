@@ -0,0 +1,366 @@
+//! CRC32C checksums of the chunks of a Superblock.
+//!
+//! If enabled on creation, the checksums of all chunks are stored in a table of Chunk<u32>
+//! right in front of the write-ahead log. They are checked when a chunk is loaded through
+//! Superblock::load.
+//!
+//! Chunks handed out for writing are marked dirty, and only the checksums of dirty chunks
+//! are recomputed when the superblock is flushed. Chunks nobody wrote keep their checksum,
+//! so flushing never hides corruption of chunks that were only read, or not touched at all.
+//! The lock table is written through atomics all the time, it is always taken as dirty.
+//! Flushes can run concurrently, the table itself is only touched while holding
+//! Superblock::checksums.
+//!
+//! The table chunks themselves are not checksummed, their entries stay 0.
+use crate::base_chunk::ChunkId;
+use crate::base_chunk::ChunkSize;
+use crate::base_chunk::Size;
+use crate::superblock::Error;
+use crate::superblock::Superblock;
+use crate::superblock::LOCK_TABLE_CHUNK;
+use core::sync::atomic::Ordering;
+use std::sync::PoisonError;
+type Chunk<T, const SIZE: usize> = crate::base_chunk::Chunk<T, usize, SIZE>;
+
+/// reflected Castagnoli polynomial
+const POLY: u32 = 0x82F6_3B78;
+
+const TABLE: [u32; 256] = table();
+
+const fn table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// CRC32C of data
+pub fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for b in data {
+        crc = TABLE[((crc ^ *b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// number of checksums that fit into one table chunk
+fn per_chunk<const SIZE: usize>() -> usize
+where
+    Size<SIZE>: ChunkSize,
+{
+    Chunk::<u32, SIZE>::BUF_SIZE / core::mem::size_of::<u32>()
+}
+
+/// number of table chunks a superblock of n_chunks chunks needs.
+pub(crate) fn table_chunks<const SIZE: usize>(n_chunks: usize) -> usize
+where
+    Size<SIZE>: ChunkSize,
+{
+    (n_chunks + per_chunk::<SIZE>() - 1) / per_chunk::<SIZE>()
+}
+
+/// writes an empty table and marks every chunk dirty,
+/// so the whole table is computed on the next flush.
+pub(crate) fn init<const SIZE: usize>(superblock: &Superblock<SIZE>)
+where
+    Size<SIZE>: ChunkSize,
+{
+    for word in superblock.dirty.iter() {
+        word.store(!0, Ordering::Relaxed);
+    }
+    let mut remaining = superblock.len();
+    for table in superblock.checksum_table.clone() {
+        // the table is not in use yet, nobody else is accessing it
+        let chunk = Chunk::initialize(unsafe { superblock.get_mut::<u32>(ChunkId::new(table)) });
+        for _ in 0..remaining.min(per_chunk()) {
+            chunk.push(0);
+        }
+        remaining = remaining.saturating_sub(per_chunk());
+    }
+}
+
+/// the table chunk and position in it holding the checksum of chunk pos,
+/// None if there are no checksums or pos is part of the table.
+fn entry<const SIZE: usize>(superblock: &Superblock<SIZE>, pos: usize) -> Option<(usize, usize)>
+where
+    Size<SIZE>: ChunkSize,
+{
+    let table = &superblock.checksum_table;
+    if table.is_empty() || table.contains(&pos) {
+        return None;
+    }
+    Some((table.start + pos / per_chunk(), pos % per_chunk()))
+}
+
+fn compute<const SIZE: usize>(superblock: &Superblock<SIZE>, pos: usize) -> u32
+where
+    Size<SIZE>: ChunkSize,
+{
+    // only reading bytes, whatever is in there is fine.
+    let bytes = unsafe {
+        let base = superblock.as_mut() as *const Chunk<u8, SIZE> as *const u8;
+        core::slice::from_raw_parts(base.add(pos * SIZE), SIZE)
+    };
+    crc32c(bytes)
+}
+
+/// recomputes the checksums of the dirty chunks among count chunks starting at pos,
+/// they are clean afterwards.
+/// returns the range of table chunks that changed, which is empty without checksums.
+pub(crate) fn update<const SIZE: usize>(
+    superblock: &Superblock<SIZE>,
+    pos: usize,
+    count: usize,
+) -> core::ops::Range<usize>
+where
+    Size<SIZE>: ChunkSize,
+{
+    // concurrent flushes would race on the table chunks.
+    // nothing in here leaves the table half written on a panic, so poisoning does not matter.
+    let _table = superblock
+        .checksums
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    let mut changed: Option<core::ops::Range<usize>> = None;
+    let end = pos + count;
+    let mut pos = pos;
+    while pos < end {
+        let (word, bit) = (pos / 64, 1 << (pos % 64));
+        let dirty = &superblock.dirty[word];
+        if dirty.load(Ordering::Relaxed) == 0 && pos != LOCK_TABLE_CHUNK {
+            // skip 64 clean chunks at once
+            pos = (word + 1) * 64;
+            continue;
+        }
+        // cleared before computing, so writes from here on make it dirty again
+        let was_dirty = dirty.fetch_and(!bit, Ordering::Relaxed) & bit != 0;
+        if !was_dirty && pos != LOCK_TABLE_CHUNK {
+            pos += 1;
+            continue;
+        }
+        if let Some((table, i)) = entry(superblock, pos) {
+            let crc = compute(superblock, pos);
+            // we are holding the table
+            let chunk = unsafe { superblock.get_mut::<u32>(ChunkId::new(table)).get_mut() };
+            chunk[i] = crc;
+            changed = Some(match changed {
+                Some(c) => c.start..table + 1,
+                None => table..table + 1,
+            });
+        }
+        pos += 1;
+    }
+    changed.unwrap_or(0..0)
+}
+
+/// checks the checksum of chunk pos, and remembers that it has been checked.
+pub(crate) fn verify<const SIZE: usize>(
+    superblock: &Superblock<SIZE>,
+    pos: usize,
+) -> Result<(), Error>
+where
+    Size<SIZE>: ChunkSize,
+{
+    if pos >= superblock.len() {
+        panic!("tried to verify a chunk that is out of bounds");
+    }
+    if let Some((table, i)) = entry(superblock, pos) {
+        let _table = superblock
+            .checksums
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        // the table is only written while holding it
+        let stored = unsafe { superblock.get::<u32>(ChunkId::new(table)).get_ref() }[i];
+        if stored != compute(superblock, pos) {
+            return Err(Error::Corrupt(ChunkId::new(pos)));
+        }
+    }
+    mark_verified(superblock, pos);
+    Ok(())
+}
+
+/// checks chunk pos, unless it has been checked or written to since the superblock was opened.
+pub(crate) fn verify_once<const SIZE: usize>(
+    superblock: &Superblock<SIZE>,
+    pos: usize,
+) -> Result<(), Error>
+where
+    Size<SIZE>: ChunkSize,
+{
+    let (word, bit) = (pos / 64, 1 << (pos % 64));
+    if superblock.verified[word].load(Ordering::Relaxed) & bit != 0 {
+        return Ok(());
+    }
+    verify(superblock, pos)
+}
+
+/// chunk pos is trusted from now on, it has been checked or is being written to.
+pub(crate) fn mark_verified<const SIZE: usize>(superblock: &Superblock<SIZE>, pos: usize)
+where
+    Size<SIZE>: ChunkSize,
+{
+    let (word, bit) = (pos / 64, 1 << (pos % 64));
+    superblock.verified[word].fetch_or(bit, Ordering::Relaxed);
+}
+
+/// chunk pos is being written to, its old checksum means nothing any more
+/// and the next flush computes a new one.
+pub(crate) fn mark_written<const SIZE: usize>(superblock: &Superblock<SIZE>, pos: usize)
+where
+    Size<SIZE>: ChunkSize,
+{
+    mark_verified(superblock, pos);
+    let (word, bit) = (pos / 64, 1 << (pos % 64));
+    superblock.dirty[word].fetch_or(bit, Ordering::Relaxed);
+}
+
+#[test]
+fn crc() {
+    assert_eq!(crc32c(b""), 0);
+    assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+}
+
+#[test]
+fn corruption() {
+    use crate::superblock::CHUNK_SIZE;
+    use std::io::Seek;
+    use std::io::Write;
+
+    let path =
+        std::env::temp_dir().join(format!("block-layer-checksum-{}.img", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    {
+        let s: Superblock = Superblock::create_checksummed(&path, 2000).unwrap();
+        assert_eq!(s.header().checksum_chunks, 2);
+        let chunk = Chunk::initialize(unsafe { s.get_mut::<u64>(ChunkId::new(3)) });
+        chunk.push(1);
+        let chunk = Chunk::initialize(unsafe { s.get_mut::<u64>(ChunkId::new(1500)) });
+        chunk.push(2);
+    }
+
+    // overwrite the first byte of chunk 3 and 1500
+    let mut file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    for pos in &[3, 1500] {
+        file.seek(std::io::SeekFrom::Start((pos * CHUNK_SIZE) as u64))
+            .unwrap();
+        file.write_all(&[0xff]).unwrap();
+    }
+    drop(file);
+
+    let s: Superblock = Superblock::open(&path).unwrap();
+    match unsafe { s.load::<u64>(ChunkId::new(3)) } {
+        Err(Error::Corrupt(id)) if id.pos() == 3 => {}
+        other => panic!("expected chunk 3 to be corrupt, got {:?}", other.err()),
+    }
+    match s.verify(ChunkId::<u64>::new(1500)) {
+        Err(Error::Corrupt(id)) if id.pos() == 1500 => {}
+        other => panic!("expected chunk 1500 to be corrupt, got {:?}", other),
+    }
+    assert!(unsafe { s.load::<u64>(ChunkId::new(4)) }.is_ok());
+
+    // writing a chunk makes it trusted, flushing fixes the checksum
+    let chunk = unsafe { s.load_mut::<u64>(ChunkId::new(5)) }.unwrap();
+    Chunk::initialize(chunk).push(5);
+    assert!(s.verify(ChunkId::<u64>::new(5)).is_err());
+    s.flush().unwrap();
+    assert!(s.verify(ChunkId::<u64>::new(5)).is_ok());
+    drop(s);
+
+    // flushing only fixes what has been written, chunk 3 stays corrupt
+    // even after opening, flushing and dropping a few times
+    for _ in 0..2 {
+        let s: Superblock = Superblock::open(&path).unwrap();
+        s.flush().unwrap();
+    }
+    let s: Superblock = Superblock::open(&path).unwrap();
+    assert!(s.verify(ChunkId::<u64>::new(3)).is_err());
+    assert!(s.verify(ChunkId::<u64>::new(5)).is_ok());
+    drop(s);
+
+    // without checksums nothing is checked
+    let s: Superblock = Superblock::anonymous(8).unwrap();
+    unsafe { s.get_mut::<u64>(ChunkId::new(3)).get_mut() }.push(1);
+    assert!(s.verify(ChunkId::<u64>::new(3)).is_ok());
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn corrupt_list() {
+    use crate::check::Violation;
+    use crate::superblock::CHUNK_SIZE;
+    use std::io::Seek;
+    use std::io::Write;
+
+    fn is_corrupt<R>(r: Result<R, Error>, pos: usize) -> bool {
+        matches!(r, Err(Error::Corrupt(id)) if id.pos() == pos)
+    }
+
+    let path = std::env::temp_dir().join(format!(
+        "block-layer-corrupt-list-{}.img",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    let freelist;
+    let (first, second): (ChunkId<u64>, ChunkId<u64>);
+    let link;
+    {
+        let s: Superblock = Superblock::create_checksummed(&path, 64).unwrap();
+        freelist = s.header().freelist as usize;
+        first = s.alloc_chunks(freelist, 1).unwrap().unwrap();
+        second = s.alloc_chunks(freelist, 1).unwrap().unwrap();
+        let chunk = Chunk::initialize(unsafe { s.get_mut(second) });
+        chunk.push(2);
+        link = unsafe { Chunk::next_hint(chunk) } as usize - chunk as *mut _ as usize;
+        let chunk = Chunk::initialize(unsafe { s.get_mut(first) });
+        chunk.push(1);
+        chunk.set_next(Some(second));
+        s.write(1).set_start(Some(first));
+    }
+
+    // second now links far outside of the superblock, and so does the freelist
+    let mut file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    let initial = crate::superblock::FREELIST_CHUNK;
+    for pos in &[second.pos(), initial] {
+        file.seek(std::io::SeekFrom::Start((pos * CHUNK_SIZE + link) as u64))
+            .unwrap();
+        file.write_all(&[0x7f; 4]).unwrap();
+    }
+    drop(file);
+
+    let mut s: Superblock = Superblock::open(&path).unwrap();
+    assert!(is_corrupt(
+        unsafe { s.read(1).cursor::<u64>() },
+        second.pos()
+    ));
+    assert!(is_corrupt(
+        unsafe { s.write(1).cursor_mut::<u64>(freelist) },
+        second.pos()
+    ));
+    assert!(is_corrupt(s.snapshot(1), second.pos()));
+    assert!(is_corrupt(s.alloc_chunks::<u8>(freelist, 1), initial));
+    let report = s.check();
+    assert!(report.violations.contains(&Violation::Corrupt {
+        list: 1,
+        chunk: second.pos()
+    }));
+    assert!(report.violations.contains(&Violation::Corrupt {
+        list: freelist,
+        chunk: initial
+    }));
+    drop(s);
+    std::fs::remove_file(&path).unwrap();
+}
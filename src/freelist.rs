@@ -1,36 +1,115 @@
+use crate::base_chunk::ChunkId;
+use crate::base_chunk::ChunkPod;
+use crate::base_chunk::ChunkSize;
+use crate::base_chunk::Size;
+use crate::base_chunk::DEFAULT_CHUNK_SIZE;
 use crate::slicelist::Cursor;
-use crate::slicelist::CursorMut;
-use std::convert::TryInto;
-use std::mem::MaybeUninit;
-
-type Chunk<T> = crate::base_chunk::Chunk<T, usize>;
-
-// todo: i fell like im missing an abstraction layer here
-// this mixes being a freelist and being a... list
-// so block allocator and chunk-list functions mixed
-// i should try and split them up.
-//
-// todo: instead of actively allocating to maintain the freelist itself
-// i should probably return an error asking for an allocation
-// so it can be embedded in other context
-pub struct FreeList<'a, T> {
-    initial: usize,
-    // ok so this is kinda inaccurate, actually i want a chunk<ANY, usize> but thats not
-    // expressible.
+use crate::slicelist::SliceList;
+use core::convert::TryInto;
+use core::mem::MaybeUninit;
+
+type Chunk<T, const SIZE: usize = DEFAULT_CHUNK_SIZE> = crate::base_chunk::Chunk<T, usize, SIZE>;
+
+/// A block allocator, keeping track of free extents of chunks.
+///
+/// The entries are stored in a SliceList in the same chunks that are being managed,
+/// this only does the bookkeeping of the extents.
+pub struct FreeList<'a, T, const SIZE: usize = DEFAULT_CHUNK_SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    // the chunks are passed as Chunk<u8>, as they are not all Chunk<Entry>.
     // another option would be a union, but they don't support stuff with drop code
     // as of now, and i can't have chunk not have drop code conditionally (see comment on chunk
     // drop impl)
-    chunks: &'a mut [MaybeUninit<Chunk<u8>>],
-    phantom: std::marker::PhantomData<T>,
+    list: SliceList<'a, Entry, SIZE>,
+    policy: Policy,
+    /// where the last allocation ended, used by Policy::NextFit
+    rover: u32,
+    /// a chunk that is not free but not part of the list either.
+    /// used when an entry chunk needs to be split,
+    /// emptied entry chunks end up here too.
+    /// only ever Some during an operation, or after provide_chunk.
+    spare: Option<ChunkId<Entry>>,
+    phantom: core::marker::PhantomData<T>,
+}
+
+/// How FreeList::allocate picks the extent to allocate from.
+///
+/// If no extent is big enough all policies fall back to the biggest one.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Policy {
+    /// the first extent that is big enough.
+    /// fast, but tends to fragment the front of the list.
+    FirstFit,
+    /// the smallest extent that is big enough.
+    /// keeps big extents around, but has to look at every extent.
+    BestFit,
+    /// the first extent of exactly the requested size,
+    /// or the first one that is big enough if there is none.
+    /// has to look at every extent unless there is an exact match.
+    ExactFit,
+    /// like FirstFit, but starts looking where the last allocation ended
+    /// and wraps around.
+    /// spreads allocations over the whole space.
+    NextFit,
+}
+
+impl Policy {
+    /// how the policy is stored in FreeList::state.
+    fn to_u8(self) -> u8 {
+        match self {
+            Policy::FirstFit => 0,
+            Policy::BestFit => 1,
+            Policy::ExactFit => 2,
+            Policy::NextFit => 3,
+        }
+    }
+
+    /// unknown values are read as FirstFit.
+    fn from_u8(v: u8) -> Self {
+        match v {
+            1 => Policy::BestFit,
+            2 => Policy::ExactFit,
+            3 => Policy::NextFit,
+            _ => Policy::FirstFit,
+        }
+    }
+}
+
+/// returned when the list needs another chunk to store its entries in.
+/// hand it one with provide_chunk and try again.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NeedsChunk;
+
+/// an extent allocate could use
+#[derive(Debug, Copy, Clone)]
+struct Candidate {
+    /// chunk in front of chunk, if there is one
+    pre: Option<ChunkId<Entry>>,
+    chunk: ChunkId<Entry>,
+    /// position of the entry inside the chunk
+    pos: usize,
+    entry: Entry,
+    /// where inside entry the allocation starts
+    at: u32,
 }
 
 #[derive(Debug, Copy, Clone)]
+#[repr(C)]
 pub struct Entry {
-    start: u32,
-    len: u32,
+    pub(crate) start: u32,
+    pub(crate) len: u32,
 }
 
+// two u32, no padding
+unsafe impl ChunkPod for Entry {}
+
 impl Entry {
+    fn new(start: u32, len: u32) -> Self {
+        Self { start, len }
+    }
+
     /// make sure you check self.len == 0 and remove after calling this.
     fn allocate(&mut self, count: u32) {
         self.start += count;
@@ -38,11 +117,14 @@ impl Entry {
     }
 }
 
-type EntryChunk = Chunk<Entry>;
+type EntryChunk<const SIZE: usize> = Chunk<Entry, SIZE>;
 
-impl EntryChunk {
+impl<const SIZE: usize> EntryChunk<SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
     /// finds count free blocks, or however many are available
-    /// will return Err((0,0)) if the chunk is empty
+    /// will return Err((0, 0)) if the chunk is empty
     /// will return coordinates _inside_ this chunk.
     pub fn find_space(&self, count: u32) -> Result<usize, (usize, u32)> {
         let mut max = (0, 0);
@@ -60,38 +142,37 @@ impl EntryChunk {
         Err(max)
     }
 
-    /// unsafety: only call thins on chunks you know have been initialized
-    /// to be Chunk<Entry>
-    pub unsafe fn from_u8(base: &MaybeUninit<Chunk<u8>>) -> &Self {
-        let chunk = base as *const _ as *const MaybeUninit<Chunk<Entry>>;
-        let chunk = chunk.as_ref().unwrap();
-        let chunk = chunk.get_ref();
-        chunk
+    /// unsafety: only call this on chunks you know have been initialized.
+    ///
+    /// panics if the chunk holds more entries than fit into it.
+    pub unsafe fn from_u8(base: &MaybeUninit<Chunk<u8, SIZE>>) -> &Self {
+        base.get_ref().view().expect("not an entry chunk")
     }
 
-    /// unsafety: only call thins on chunks you know have been initialized
-    /// to be Chunk<Entry>
-    pub unsafe fn from_u8_mut(base: &mut MaybeUninit<Chunk<u8>>) -> &mut Self {
-        let chunk = base as *mut _ as *mut MaybeUninit<Chunk<Entry>>;
-        let chunk = chunk.as_mut().unwrap();
-        let chunk = chunk.get_mut();
-        chunk
+    /// unsafety: only call this on chunks you know have been initialized.
+    ///
+    /// panics if the chunk holds more entries than fit into it.
+    pub unsafe fn from_u8_mut(base: &mut MaybeUninit<Chunk<u8, SIZE>>) -> &mut Self {
+        base.get_mut().view_mut().expect("not an entry chunk")
     }
 }
-impl<'a, 'b, T> IntoIterator for &'b FreeList<'a, T>
+impl<'a, 'b, T, const SIZE: usize> IntoIterator for &'b FreeList<'a, T, SIZE>
 where
     'b: 'a,
+    Size<SIZE>: ChunkSize,
 {
-    type Item = (usize, &'a Chunk<Entry>);
-    type IntoIter = Cursor<'a, Entry>;
-    fn into_iter(self) -> <Self as std::iter::IntoIterator>::IntoIter {
-        // this is ok, the freelist is always in a consistent state
-        unsafe { Cursor::from_byteslice(&*self.chunks, self.initial) }
+    type Item = (ChunkId<Entry>, &'a Chunk<Entry, SIZE>);
+    type IntoIter = Cursor<'a, Entry, SIZE>;
+    fn into_iter(self) -> <Self as core::iter::IntoIterator>::IntoIter {
+        self.list.chunks()
     }
 }
 
-impl<'a, T> std::fmt::Debug for FreeList<'a, T> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+impl<'a, T, const SIZE: usize> core::fmt::Debug for FreeList<'a, T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::result::Result<(), core::fmt::Error> {
         let mut list = f.debug_list();
         for (id, chunk) in self.into_iter() {
             list.entry(&(id, chunk));
@@ -100,29 +181,31 @@ impl<'a, T> std::fmt::Debug for FreeList<'a, T> {
     }
 }
 
-impl<'a, T> FreeList<'a, T> {
+impl<'a, T, const SIZE: usize> FreeList<'a, T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
     /// creates a new FreeList, writing its initial chunk at initial.
     /// during initialization only indices >= initial are touched
     /// so you can safely put data in front of initial
     /// and later manually mark it as used.
-    pub fn new(c: &'a mut [MaybeUninit<Chunk<u8>>], initial: u32) -> Self {
+    pub fn new(c: &'a mut [MaybeUninit<Chunk<u8, SIZE>>], initial: ChunkId<Entry>) -> Self {
         let len: u32 = c
             .len()
             .try_into()
             .expect("passed slice has more than 32bit chunks");
-        let base = &mut c[initial as usize];
-        // should be safe, chunk has way higher alignment than entry
-        let base = unsafe {
-            (base as *mut MaybeUninit<Chunk<u8>> as *mut MaybeUninit<Chunk<Entry>>).as_mut()
-        }
-        .unwrap();
-        let base = Chunk::initialize(base);
+        let mut list = SliceList::new(c, initial);
+        // initial is the only chunk of the list
+        let base = unsafe { list.get_mut(initial) };
+        let initial = initial.pos() as u32;
 
         // write initial entries
-        base.push(Entry {
-            start: 0,
-            len: initial,
-        });
+        if initial > 0 {
+            base.push(Entry {
+                start: 0,
+                len: initial,
+            });
+        }
         let remain = len.saturating_sub(initial + 1);
         if remain > 0 {
             base.push(Entry {
@@ -133,29 +216,236 @@ impl<'a, T> FreeList<'a, T> {
 
         // and thats it for initialization, other chunks are never touched.
         Self {
-            initial: initial as usize,
-            chunks: c,
+            list,
+            policy: Policy::FirstFit,
+            rover: 0,
+            spare: None,
             phantom: Default::default(),
         }
     }
 
     /// reads a previously created freelist,
+    /// state is what state() returned back then, 0 for a fresh list.
+    ///
     /// safety: make sure the list is actually been previously initialized
     /// don't just pass thing uninitialized data.
     ///
     /// also make sure the offsets are the same as previously.
-    pub unsafe fn new_from(c: &'a mut [MaybeUninit<Chunk<u8>>], initial: usize) -> Self {
+    pub unsafe fn new_from(
+        c: &'a mut [MaybeUninit<Chunk<u8, SIZE>>],
+        initial: ChunkId<Entry>,
+        state: u64,
+    ) -> Self {
+        Self::from_raw(c.as_mut_ptr(), c.len(), initial, state)
+    }
+
+    /// like new_from, but on the len chunks starting at c.
+    /// only the chunks of the list are ever referenced, see SliceList::from_raw.
+    ///
+    /// safety: everything new_from states, and the chunks need to stay valid for 'a.
+    pub unsafe fn from_raw(
+        c: *mut MaybeUninit<Chunk<u8, SIZE>>,
+        len: usize,
+        initial: ChunkId<Entry>,
+        state: u64,
+    ) -> Self {
         Self {
-            initial,
-            chunks: c,
+            list: SliceList::from_raw(c, len, Some(initial)),
+            policy: Policy::from_u8((state >> 32) as u8),
+            rover: state as u32,
+            spare: None,
             phantom: Default::default(),
         }
     }
 
-    /// marks a location as used, returns false if the location was already used.
-    pub fn mark_used(&mut self, pos: usize) -> bool {
-        println!("{}", pos);
-        todo!()
+    /// how extents are picked on allocation, Policy::FirstFit unless changed.
+    pub fn policy(&self) -> Policy {
+        self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: Policy) {
+        self.policy = policy;
+    }
+
+    /// the policy and where the last allocation ended, packed into one word.
+    /// persist it next to initial() and hand it to new_from() to continue from there.
+    pub fn state(&self) -> u64 {
+        (self.policy.to_u8() as u64) << 32 | self.rover as u64
+    }
+
+    /// the first chunk of this list.
+    /// this may change on allocation, persist it if you want to new_from() later on.
+    pub fn initial(&self) -> ChunkId<Entry> {
+        self.list.start().expect("the list always keeps one chunk")
+    }
+
+    /// marks count chunks starting at pos as used, splitting up free extents as necessary.
+    /// returns false if any part of the range was already used,
+    /// the rest of the range is marked as used regardless.
+    ///
+    /// chunks for the list itself are taken from the list, see try_mark_used if you don't want that.
+    pub fn mark_used(&mut self, pos: u32, count: u32) -> bool {
+        loop {
+            match self.try_mark_used(pos, count) {
+                Ok(res) => return res,
+                Err(NeedsChunk) => self.provide_own_chunk(),
+            }
+        }
+    }
+
+    /// like mark_used, but returns Err(NeedsChunk) without changing anything
+    /// if the list needs another chunk for its entries, see provide_chunk.
+    pub fn try_mark_used(&mut self, pos: u32, count: u32) -> Result<bool, NeedsChunk> {
+        let end = pos + count;
+        let mut found = 0;
+        let mut pre = None;
+        let mut id = self.initial();
+        // emptied chunks can only be freed once we are done walking the list,
+        // freeing them may change the list.
+        // until then they are chained through their next_hint, no allocation needed.
+        let mut retired: Option<ChunkId<Entry>> = None;
+        loop {
+            // id is always part of the list
+            let chunk = unsafe { self.list.get_mut(id) };
+            // the first entry that ends after pos
+            let mut i = match chunk.binary_search_by_key(&pos, |e| e.start + e.len) {
+                Ok(i) => i + 1,
+                Err(i) => i,
+            };
+            let mut done = false;
+            while let Some(e) = chunk.get(i).copied() {
+                let e_end = e.start + e.len;
+                if e.start >= end {
+                    done = true;
+                    break;
+                }
+                found += e_end.min(end) - e.start.max(pos);
+                if e.start < pos && e_end > end {
+                    // the range is in the middle of a single extent.
+                    // this is the first extent we look at, so nothing changed yet.
+                    if chunk.len() == chunk.capacity() && self.spare.is_none() {
+                        return Err(NeedsChunk);
+                    }
+                    chunk[i].len = pos - e.start;
+                    assert!(self.insert(id, i + 1, Entry::new(end, e_end - end)).is_ok());
+                    self.release_spare();
+                    return Ok(found == count);
+                } else if e.start < pos {
+                    chunk[i].len = pos - e.start;
+                    i += 1;
+                } else if e_end > end {
+                    chunk[i] = Entry::new(end, e_end - end);
+                    done = true;
+                    break;
+                } else {
+                    chunk.remove(i);
+                }
+            }
+
+            let next = chunk.next();
+            // always keep at least one chunk
+            if chunk.len() == 0 && (pre.is_some() || next.is_some()) {
+                unsafe { self.list.unlink(pre, id) };
+                // just unlinked, nobody else uses the chunk and its link is free to use
+                unsafe { self.list.get_mut(id) }.set_next(retired);
+                retired = Some(id);
+            } else {
+                pre = Some(id);
+            }
+            match next {
+                Some(next) if !done => id = next,
+                _ => break,
+            }
+        }
+        while let Some(id) = retired {
+            // read the link before retire reuses the chunk
+            retired = unsafe { self.list.get_mut(id) }.next();
+            self.retire(id);
+        }
+        self.release_spare();
+        Ok(found == count)
+    }
+
+    /// marks the chunks in range as used, see mark_used.
+    pub fn reserve_range(&mut self, range: core::ops::Range<u32>) -> bool {
+        self.mark_used(range.start, range.end.saturating_sub(range.start))
+    }
+
+    /// hands the list an unused chunk, after it returned Err(NeedsChunk).
+    /// the chunk belongs to the list from now on.
+    /// the next try_free or try_mark_used uses it,
+    /// if it ends up not being needed it is freed into the list.
+    ///
+    /// safety: pos must be a chunk nobody else uses, and that is not free in this list.
+    ///
+    /// panics if the previously provided chunk was not used yet.
+    pub unsafe fn provide_chunk(&mut self, id: ChunkId<Entry>) {
+        assert!(self.spare.is_none(), "a chunk was already provided");
+        self.spare = Some(id);
+    }
+
+    /// allocates a chunk for the list from the list itself and provides it.
+    /// it is taken from the start of an extent, so this never needs a chunk itself,
+    /// and the rover stays where it is.
+    fn provide_own_chunk(&mut self) {
+        let policy = core::mem::replace(&mut self.policy, Policy::FirstFit);
+        let rover = self.rover;
+        let res = self.allocate(1);
+        self.policy = policy;
+        self.rover = rover;
+        match res {
+            // the chunk was free, now nobody uses it
+            Ok(id) => unsafe { self.provide_chunk(id.cast()) },
+            Err(_) => panic!("freelist exhausted, can't allocate a chunk for its own entries"),
+        }
+    }
+
+    /// takes care of an entry chunk that is no longer part of the list.
+    /// it becomes the spare chunk if there is none, otherwise it is freed.
+    /// freeing can't fail, as there is a spare chunk then.
+    fn retire(&mut self, id: ChunkId<Entry>) {
+        match self.spare {
+            None => self.spare = Some(id),
+            Some(_) => unsafe { self.free_inner(id.pos() as u32, 1) }.unwrap(),
+        }
+    }
+
+    /// puts the spare chunk back into the list, if there is one.
+    /// ran at the end of every operation, so the spare never outlives it.
+    fn release_spare(&mut self) {
+        // freeing can empty a chunk which then becomes the spare
+        while let Some(spare) = self.spare.take() {
+            // freeing it might need a new entry chunk, the spare itself can serve as that.
+            if let Err(id) = unsafe { self.free_inner(spare.pos() as u32, 1) } {
+                // id is part of the list, the spare is not
+                unsafe { self.list.split(id, spare) };
+            }
+        }
+    }
+
+    /// inserts entry at position pos of chunk id.
+    /// if the chunk is full it is split in half, using the spare chunk.
+    ///
+    /// returns Err(id) without changing anything if there is no spare chunk but it's needed.
+    fn insert(
+        &mut self,
+        id: ChunkId<Entry>,
+        pos: usize,
+        entry: Entry,
+    ) -> Result<(), ChunkId<Entry>> {
+        // id is always part of the list
+        let chunk = unsafe { self.list.get_mut(id) };
+        let entry = match chunk.insert(pos, entry) {
+            Ok(_) => return Ok(()),
+            Err(entry) => entry,
+        };
+        let spare = match self.spare.take() {
+            Some(spare) => spare,
+            None => return Err(id),
+        };
+        // the spare chunk is not used by anyone
+        unsafe { self.list.insert_split(id, pos, entry, spare) };
+        Ok(())
     }
 
     /// marks a location as free
@@ -168,10 +458,38 @@ impl<'a, T> FreeList<'a, T> {
     /// used before calling this.
     ///
     /// will panic if trying to free something that is not marked as used.
+    ///
+    /// chunks for the list itself are taken from the list, see try_free if you don't want that.
+    pub unsafe fn free(&mut self, id: ChunkId<T>, count: u32) {
+        loop {
+            match self.try_free(id, count) {
+                Ok(()) => return,
+                Err(NeedsChunk) => self.provide_own_chunk(),
+            }
+        }
+    }
+
+    /// like free, but returns Err(NeedsChunk) without changing anything
+    /// if the list needs another chunk for its entries, see provide_chunk.
+    ///
+    /// this way the list never allocates from itself,
+    /// so it can be embedded into other allocators.
+    pub unsafe fn try_free(&mut self, id: ChunkId<T>, count: u32) -> Result<(), NeedsChunk> {
+        let res = self
+            .free_inner(id.pos() as u32, count)
+            .map_err(|_id| NeedsChunk);
+        if res.is_ok() {
+            self.release_spare();
+        }
+        res
+    }
+
+    /// frees without touching the spare chunk unless a new entry chunk is needed.
+    /// returns Err(id) with the full chunk if that is the case and there is no spare chunk.
     // todo: move entire code into inner non-unsafe fn so unsafe is more visible
-    pub unsafe fn free(&mut self, pos: u32, count: u32) {
+    unsafe fn free_inner(&mut self, pos: u32, count: u32) -> Result<(), ChunkId<Entry>> {
         let mut free_chunk = None;
-        let mut iter = CursorMut::from_byteslice(self.chunks, self.initial);
+        let mut iter = self.list.chunks_mut();
         while let Some((id, chunk)) = iter.next() {
             // generally empty chunks are forbidden
             // but its fine if its the initial chunk
@@ -261,12 +579,8 @@ impl<'a, T> FreeList<'a, T> {
                 chunk[insert_pos - 1].len += count + post_entry.len;
 
                 if rem {
-                    let next_next = next.next_hint;
-
-                    chunk.next_hint = next_next;
-                    std::ptr::drop_in_place(next as *mut _);
-
-                    self.free(next_id as u32, 1);
+                    self.list.unlink(Some(id), next_id);
+                    self.retire(next_id);
                 } else {
                     // it would be possible to balance if this and next are very un-equally full
                     // or merge if both are quite empty
@@ -275,110 +589,113 @@ impl<'a, T> FreeList<'a, T> {
                 }
             }
             (false, PostAdj::No) => {
-                // this is the most complicated case: add a new entry, possibly allocating a chunk
-                // but we can't really allocate right now since that would invalidate all
-                // the work we just did.
-                // we _can_ just quickly "steal" some space from the full chunk though.
-
+                // add a new entry, this might need a new chunk if this one is full.
+                // nothing has been changed so far, so we can still bail out.
                 let entry = Entry {
                     start: pos,
                     len: count,
                 };
-                let succ = chunk.insert(insert_pos, entry);
-
-                match succ {
-                    // we are good, chunk still had space left
-                    Ok(_) => {}
-                    // oh no, we gotta do something
-                    Err(entry) => {
-                        // by definition the chunk is full, so allocate one element from the last
-                        // entry
-                        let last = &mut chunk.last_mut().unwrap();
-                        last.len -= 1;
-                        let newchunk = last.start + last.len;
-                        if last.len == 0 {
-                            chunk.pop().unwrap();
+                return self.insert(id, insert_pos, entry);
+            }
+        }
+        Ok(())
+    }
+
+    /// picks the extent to allocate count chunks from, according to the policy.
+    /// returns None if there are no free chunks at all.
+    fn find(&self, count: u32) -> Option<Candidate> {
+        let mut largest: Option<Candidate> = None;
+        // the best fit so far, for the policies that look at more than one fitting extent
+        let mut fit: Option<Candidate> = None;
+        let mut pre = None;
+
+        for (id, chunk) in self.list.chunks() {
+            for (pos, entry) in chunk.iter().enumerate() {
+                let candidate = Candidate {
+                    pre,
+                    chunk: id,
+                    pos,
+                    entry: *entry,
+                    at: entry.start,
+                };
+                if largest.map_or(true, |l| entry.len > l.entry.len) {
+                    largest = Some(candidate);
+                }
+                if entry.len < count {
+                    continue;
+                }
+                match self.policy {
+                    Policy::FirstFit => return Some(candidate),
+                    Policy::BestFit | Policy::ExactFit if entry.len == count => {
+                        return Some(candidate)
+                    }
+                    Policy::BestFit => {
+                        if fit.map_or(true, |f| entry.len < f.entry.len) {
+                            fit = Some(candidate);
+                        }
+                    }
+                    Policy::ExactFit => {
+                        if fit.is_none() {
+                            fit = Some(candidate);
+                        }
+                    }
+                    Policy::NextFit => {
+                        // the extent might start in front of the rover and still have room past it
+                        let end = entry.start + entry.len;
+                        let at = entry.start.max(self.rover);
+                        if end > self.rover && end - at >= count {
+                            return Some(Candidate { at, ..candidate });
+                        }
+                        // in case we need to wrap around
+                        if fit.is_none() {
+                            fit = Some(candidate);
                         }
-                        let next = chunk.next_hint;
-                        let newchunk_ref = &mut self.chunks[newchunk as usize];
-                        let newchunk_ref = (newchunk_ref as *mut _
-                            as *mut MaybeUninit<Chunk<Entry>>)
-                            .as_mut()
-                            .unwrap();
-                        // this re-borrow is kinda hard to avoid
-                        // -possilbe with split_mut- but still annoying
-                        let chunk = &mut self.chunks[id];
-                        let chunk = EntryChunk::from_u8_mut(chunk);
-                        // split
-                        // todo: maybe split in the middle instead of at insert pos
-                        let new = chunk.split(insert_pos, newchunk_ref);
-                        // re-connect link
-                        new.next_hint = next;
-                        chunk.next_hint = newchunk as usize;
-
-                        // insert, needs to succeed now, since we just split the chunk
-                        chunk.push(entry).unwrap_none();
                     }
                 }
             }
+            pre = Some(id);
         }
+        fit.or(largest)
     }
 
     /// tries to allocate count adjacent chunks
     /// if successful returns Ok(pos) with the position of the first chunk
     ///
     /// if there is not that much adjacent free space returns
-    /// Err(pos, len) with the position of the first chunk, and the len
+    /// Err(id, len) with the first chunk, and the len
     /// that was successfully allocated.
     /// if len != 0 you can then re-call this with the remaining chunks you need
-    /// until your needs have been met.
+    /// until your needs have been met. if len == 0 nothing is free and id means nothing.
+    ///
+    /// which free space is used is decided by the policy.
+    ///
+    /// this only needs an additional chunk for the list itself if NextFit allocates from the
+    /// middle of an extent, that chunk is taken from the list.
+    /// entry chunks that become empty are freed.
     ///
-    /// todo: add option to prefer exact match
     /// todo: if an allocation empties out a chunk: move the next chunk into this chunk
     /// todo: (or connect the previous to the next chunk).
-    pub fn allocate(&mut self, count: u32) -> Result<usize, (usize, u32)> {
-        // list is initialized
-        use crate::slicelist::IterExt;
-        let iter = unsafe { CursorMut::<Entry>::from_byteslice(self.chunks, self.initial) };
-        let mut iter = iter.filter_map(|(c_id, chunk)| {
-            let max = chunk
-                .iter_mut()
-                .enumerate()
-                .max_by_key_with_cutoff(|(_, e)| e.len, count)?;
-            let max = (max.1.len, max.0);
-            Some((c_id, chunk, max))
-        });
-
-        let mut max = if let Some(e) = iter.next() {
-            e
-        } else {
-            return Err((0, 0));
+    pub fn allocate(&mut self, count: u32) -> Result<ChunkId<T>, (ChunkId<T>, u32)> {
+        let Candidate {
+            pre,
+            chunk: chunk_id,
+            pos,
+            entry,
+            at,
+        } = match self.find(count) {
+            Some(candidate) => candidate,
+            None => return Err((ChunkId::new(0), 0)),
         };
-
-        let mut pre = None;
-
-        // this is not actually a loop this is a block that i can jump out of.
-        // maybe i can express this nicer? with a do-while loop maybe? not sure
-        'goto: loop {
-            if max.2 .0 >= count {
-                break 'goto;
-            }
-            for (c_id, chunk, in_chunk) in iter {
-                if in_chunk.0 > max.2 .0 {
-                    max = (c_id, chunk, in_chunk);
-                    if max.2 .0 >= count {
-                        break 'goto;
-                    }
-                }
-
-                pre = Some(c_id);
-            }
-
-            break 'goto;
+        if at != entry.start {
+            // the part in front of the rover stays free, find made sure the rest fits count.
+            assert!(self.mark_used(at, count));
+            self.rover = at + count;
+            return Ok(ChunkId::new(at as usize));
         }
 
-        let (chunk_id, chunk, in_chunk) = max;
-        let free_entry = &mut chunk[in_chunk.1];
+        // find only returns chunks that are part of the list
+        let chunk = unsafe { self.list.get_mut(chunk_id) };
+        let free_entry = &mut chunk[pos];
 
         let to_alloc = count.min(free_entry.len);
 
@@ -387,51 +704,24 @@ impl<'a, T> FreeList<'a, T> {
         free_entry.allocate(to_alloc);
 
         if free_entry.len == 0 {
-            chunk.remove(in_chunk.1);
+            chunk.remove(pos);
         }
+        self.rover = start as u32 + to_alloc;
 
         // remove chunk if empty
-        if chunk.len() == 0 {
-            if let Some(pre) = pre {
-                let next_hint = chunk.next_hint;
-                // nothing references the chunk any more, time to drop it
-                unsafe {
-                    std::ptr::drop_in_place(chunk as *mut _);
-                }
-                // pre is a valid chunk, by definition
-                let pre_ref = unsafe {
-                    let pre_ref = &mut self.chunks[pre];
-                    let pre_ref = (pre_ref as *mut _ as *mut MaybeUninit<Chunk<Entry>>)
-                        .as_mut()
-                        .unwrap();
-                    pre_ref.get_mut()
-                };
-                pre_ref.next_hint = next_hint;
-                unsafe {
-                    self.free(chunk_id as u32, 1);
-                }
-            } else {
-                // this is the first chunk, there is no previous one
-                // so we just change what we consider the initial chunk
-                use crate::base_chunk::Link;
-                // always keep at least one chunk, otherwise we can never
-                // free anything again
-                if !Link::<Chunk<Entry>>::is_empty(&chunk.next_hint) {
-                    self.initial = chunk.next_hint;
-                    unsafe {
-                        std::ptr::drop_in_place(chunk as *mut _);
-                    }
-                    unsafe {
-                        self.free(chunk_id as u32, 1);
-                    }
-                }
-            }
+        // always keep at least one chunk, otherwise we can never
+        // free anything again
+        if chunk.len() == 0 && (pre.is_some() || chunk.has_next()) {
+            // pre and chunk_id are part of the list
+            unsafe { self.list.unlink(pre, chunk_id) };
+            self.retire(chunk_id);
         }
+        self.release_spare();
 
         if to_alloc == count {
-            Ok(start)
+            Ok(ChunkId::new(start))
         } else {
-            Err((start, to_alloc))
+            Err((ChunkId::new(start), to_alloc))
         }
     }
 }
@@ -440,7 +730,7 @@ impl<'a, T> FreeList<'a, T> {
 fn alloc_free() {
     fn count_free_chunks<'a, T>(l: &FreeList<'a, T>) -> usize {
         l.into_iter()
-            .flat_map(|(_, e)| std::ops::Deref::deref(e))
+            .elements()
             .map(|e| e.len as usize)
             .sum::<usize>()
     };
@@ -470,19 +760,19 @@ fn alloc_free() {
                 let alloc = freelist.allocate(size);
                 check_disjunct(&freelist);
                 match alloc {
-                    Ok(pos) => {
+                    Ok(id) => {
                         allocations.push(Entry {
-                            start: pos as u32,
+                            start: id.pos() as u32,
                             len: size,
                         });
                         break 'retry;
                     }
-                    Err((pos, len)) => {
+                    Err((id, len)) => {
                         if len == 0 {
                             break 'outer;
                         };
                         allocations.push(Entry {
-                            start: pos as u32,
+                            start: id.pos() as u32,
                             len,
                         });
                         size -= len;
@@ -507,7 +797,7 @@ fn alloc_free() {
             let alloc = allocations.remove(alloc);
             let pre = count_free_chunks(&freelist);
             unsafe {
-                freelist.free(alloc.start, alloc.len);
+                freelist.free(ChunkId::new(alloc.start as usize), alloc.len);
             }
             let post = count_free_chunks(&freelist);
             // needs to have freed the whole allocation
@@ -533,7 +823,7 @@ fn alloc_free() {
     let mut base = Vec::with_capacity(n_chunks);
     unsafe { base.set_len(n_chunks) };
 
-    let mut freelist = FreeList::<u8>::new(&mut base, 5);
+    let mut freelist = FreeList::<u8>::new(&mut base, ChunkId::new(5));
     use rand;
     use rand::Rng;
     let mut rng = rand::thread_rng();
@@ -544,8 +834,7 @@ fn alloc_free() {
     dbg!(&freelist);
     // can reach a "metastable" state right now
     // where allocations are only used to keep freelist chunks
-    let chunk = &freelist.chunks[freelist.initial];
-    let chunk = unsafe { EntryChunk::from_u8(chunk) };
+    let chunk = unsafe { freelist.list.get(freelist.initial()) };
     assert!(!chunk.has_next());
     assert_eq!(chunk.len(), 2);
     assert_eq!(count_free_chunks(&freelist), n_chunks - 1);
@@ -559,9 +848,160 @@ fn alloc_free() {
     free(&mut allocations, &mut freelist, len, &mut rng);
 
     dbg!(&freelist);
-    let chunk = &freelist.chunks[freelist.initial];
-    let chunk = unsafe { EntryChunk::from_u8(chunk) };
+    let chunk = unsafe { freelist.list.get(freelist.initial()) };
     assert!(!chunk.has_next());
     assert_eq!(chunk.len(), 2);
     assert_eq!(count_free_chunks(&freelist), n_chunks - 1);
 }
+
+#[test]
+fn policies() {
+    fn fragmented(base: &mut [MaybeUninit<Chunk<u8>>], policy: Policy) -> FreeList<u8> {
+        let mut freelist = FreeList::<u8>::new(base, ChunkId::new(1));
+        freelist.set_policy(policy);
+        assert_eq!(freelist.allocate(1), Ok(ChunkId::new(0)));
+        assert_eq!(freelist.allocate(98), Ok(ChunkId::new(2)));
+        assert_eq!(freelist.allocate(1), Err((ChunkId::new(0), 0)));
+        for &(pos, count) in &[(10, 5), (30, 3), (50, 8), (70, 3)] {
+            unsafe { freelist.free(ChunkId::new(pos), count) };
+        }
+        // the rover is behind every free extent, so NextFit starts over at the front
+        freelist
+    }
+    let n_chunks = 100;
+    let mut base = Vec::with_capacity(n_chunks);
+    unsafe { base.set_len(n_chunks) };
+
+    let mut freelist = fragmented(&mut base, Policy::FirstFit);
+    assert_eq!(freelist.allocate(3), Ok(ChunkId::new(10)));
+    assert_eq!(freelist.allocate(4), Ok(ChunkId::new(50)));
+    assert_eq!(freelist.allocate(9), Err((ChunkId::new(54), 4)));
+
+    let mut freelist = fragmented(&mut base, Policy::BestFit);
+    assert_eq!(freelist.allocate(3), Ok(ChunkId::new(30)));
+    assert_eq!(freelist.allocate(4), Ok(ChunkId::new(10)));
+    assert_eq!(freelist.allocate(3), Ok(ChunkId::new(70)));
+    assert_eq!(freelist.allocate(9), Err((ChunkId::new(50), 8)));
+
+    let mut freelist = fragmented(&mut base, Policy::ExactFit);
+    assert_eq!(freelist.allocate(3), Ok(ChunkId::new(30)));
+    assert_eq!(freelist.allocate(4), Ok(ChunkId::new(10)));
+    assert_eq!(freelist.allocate(1), Ok(ChunkId::new(14)));
+
+    let mut freelist = fragmented(&mut base, Policy::NextFit);
+    assert_eq!(freelist.allocate(3), Ok(ChunkId::new(10)));
+    assert_eq!(freelist.allocate(3), Ok(ChunkId::new(30)));
+    assert_eq!(freelist.allocate(1), Ok(ChunkId::new(50)));
+    // reading the list back continues where it left off, FirstFit would pick 13 now
+    let (initial, state) = (freelist.initial(), freelist.state());
+    let mut freelist = unsafe { FreeList::<u8>::new_from(&mut base, initial, state) };
+    assert_eq!(freelist.policy(), Policy::NextFit);
+    assert_eq!(freelist.allocate(1), Ok(ChunkId::new(51)));
+    assert_eq!(freelist.allocate(3), Ok(ChunkId::new(52)));
+    assert_eq!(freelist.allocate(3), Ok(ChunkId::new(55)));
+    assert_eq!(freelist.allocate(2), Ok(ChunkId::new(70)));
+    // wraps around
+    assert_eq!(freelist.allocate(2), Ok(ChunkId::new(13)));
+
+    // freeing the chunks in front of the rover puts it in the middle of an extent
+    let mut freelist = fragmented(&mut base, Policy::NextFit);
+    assert_eq!(freelist.allocate(3), Ok(ChunkId::new(10)));
+    unsafe { freelist.free(ChunkId::new(10), 3) };
+    assert_eq!(freelist.allocate(1), Ok(ChunkId::new(13)));
+    // the rest of the extent is too small, the next one fits
+    assert_eq!(freelist.allocate(2), Ok(ChunkId::new(30)));
+    let free: Vec<(u32, u32)> = freelist
+        .into_iter()
+        .elements()
+        .map(|e| (e.start, e.len))
+        .collect();
+    assert_eq!(free, vec![(10, 3), (14, 1), (32, 1), (50, 8), (70, 3)]);
+}
+
+#[test]
+fn mark_used() {
+    fn free_in<T>(l: &FreeList<T>, range: std::ops::Range<u32>) -> u32 {
+        l.into_iter()
+            .elements()
+            .map(|e| {
+                (e.start + e.len)
+                    .min(range.end)
+                    .saturating_sub(e.start.max(range.start))
+            })
+            .sum()
+    }
+    let n_chunks = 2000;
+    let mut base = Vec::with_capacity(n_chunks);
+    unsafe { base.set_len(n_chunks) };
+    let mut freelist = FreeList::<u8>::new(&mut base, ChunkId::new(1));
+    assert_eq!(free_in(&freelist, 0..2000), 1999);
+
+    // in front of the initial chunk, then in the middle of an extent
+    assert!(freelist.mark_used(0, 1));
+    assert!(freelist.mark_used(100, 10));
+    assert!(!freelist.mark_used(0, 1));
+    assert!(!freelist.mark_used(105, 10));
+    assert_eq!(free_in(&freelist, 100..115), 0);
+    assert_eq!(free_in(&freelist, 0..2000), 1998 - 15);
+    assert_eq!(freelist.allocate(98), Ok(ChunkId::new(2)));
+
+    // lots of small extents, so the entry chunk has to be split.
+    // the new entry chunks are allocated from the list itself.
+    for pos in (200..1990).step_by(2) {
+        assert!(freelist.reserve_range(pos..pos + 1));
+    }
+    assert!(freelist.into_iter().count() > 1);
+    assert_eq!(free_in(&freelist, 200..2000), 895 + 10);
+    assert!(free_in(&freelist, 115..200) < 85);
+
+    // spanning many extents and chunks
+    assert!(!freelist.mark_used(200, 1800));
+    // the emptied entry chunks are free again
+    assert_eq!(free_in(&freelist, 200..2000), 0);
+    assert_eq!(free_in(&freelist, 115..200), 85);
+}
+
+#[test]
+fn needs_chunk() {
+    fn entries<T>(l: &FreeList<T>) -> Vec<(u32, u32)> {
+        l.into_iter().elements().map(|e| (e.start, e.len)).collect()
+    }
+    let n_chunks = 2000;
+    let mut base = Vec::with_capacity(n_chunks);
+    unsafe { base.set_len(n_chunks) };
+    let mut freelist = FreeList::<u8>::new(&mut base, ChunkId::new(0));
+    assert_eq!(freelist.allocate(1999), Ok(ChunkId::new(1)));
+    assert_eq!(entries(&freelist), vec![]);
+
+    // the chunks at the end are handed out by the "outer" allocator
+    let mut outer = 1999;
+    for pos in (1..1900).step_by(2) {
+        loop {
+            let before = entries(&freelist);
+            match unsafe { freelist.try_free(ChunkId::new(pos as usize), 1) } {
+                Ok(()) => break,
+                Err(NeedsChunk) => {
+                    assert_eq!(entries(&freelist), before);
+                    unsafe { freelist.provide_chunk(ChunkId::new(outer as usize)) };
+                    outer -= 1;
+                }
+            }
+        }
+    }
+    assert!(outer < 1999);
+    assert!(entries(&freelist)
+        .into_iter()
+        .eq((1..1900).step_by(2).map(|pos| (pos, 1))));
+
+    // merging everything empties the entry chunks in between, they are freed.
+    // the last provided chunk is still needed for the entries behind 1900.
+    for pos in (2..1900).step_by(2) {
+        unsafe { freelist.try_free(ChunkId::new(pos as usize), 1) }.unwrap();
+    }
+    let ids: Vec<_> = freelist.into_iter().map(|(id, _chunk)| id.pos()).collect();
+    assert_eq!(ids, vec![0, outer as usize + 1]);
+    let freed = (outer + 2..2000).map(|pos| (pos, 1));
+    assert!(entries(&freelist)
+        .into_iter()
+        .eq(std::iter::once((1, 1899)).chain(freed)));
+}
@@ -1,5 +1,8 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+#![feature(const_generics_defaults)]
 #![feature(maybe_uninit_extra)]
 #![feature(maybe_uninit_ref)]
+#![feature(min_const_generics)]
 #![feature(new_uninit)]
 #![feature(option_unwrap_none)]
 //! A List of singly-linked Chunks.
@@ -24,16 +27,46 @@
 //! Could be useful if you have multiple MB of Data that you need to add/delete in
 //! Like when you are implementing a database for example.
 //!
-//! Storing the chunks in a Memory-mapped file will also be supported,
-//! tough the indexing will look a bit more complicated.
+//! The chunks can also be stored in a memory-mapped file, see [`superblock::Superblock`].
+//! The indexing looks a bit more complicated there, as chunks reference each other by offset.
+//!
+//! ## Cargo features
+//!
+//! * `std` (default): the [`superblock::Superblock`] and the lists stored in it, implies `alloc`.
+//! * `alloc`: [`anchor::Anchor`], which keeps its chunks on the heap.
+//!
+//! Without them the crate is `no_std`.
+//! Chunks, [`freelist::FreeList`] and [`slicelist`] only need a slice of chunks,
+//! like a static page array.
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
 
 mod base_chunk;
 pub use base_chunk::Chunk;
+pub use base_chunk::ChunkId;
+pub use base_chunk::ChunkLen;
+pub use base_chunk::ChunkPod;
+pub use base_chunk::ChunkSize;
+pub use base_chunk::Size;
+pub use base_chunk::DEFAULT_CHUNK_SIZE;
 
+#[cfg(feature = "alloc")]
 pub mod anchor;
+#[cfg(feature = "std")]
+pub mod check;
+#[cfg(feature = "std")]
+pub mod checksum;
 pub mod freelist;
 pub mod ptrlist;
+#[cfg(feature = "std")]
 pub mod rle;
 pub mod slicelist;
+#[cfg(feature = "std")]
+pub mod snapshot;
+#[cfg(feature = "std")]
 pub mod sorted_list;
+#[cfg(feature = "std")]
 pub mod superblock;
+#[cfg(feature = "std")]
+pub mod wal;
@@ -1,62 +1,81 @@
-use crate::base_chunk::Link;
+use crate::base_chunk::ChunkId;
+use crate::base_chunk::ChunkPod;
+use crate::slicelist::check_len;
 type Chunk<T> = crate::base_chunk::Chunk<T, usize>;
+use core::iter::FusedIterator;
 use core::marker::PhantomData;
 
 #[derive(Clone, Copy)]
 pub struct Cursor<'a, T> {
     data: *const Chunk<T>,
-    current: usize,
+    current: Option<ChunkId<T>>,
     phantom: PhantomData<&'a T>,
 }
 
 impl<'a, T> Cursor<'a, T> {
+    /// like Chunk::view, T is checked to have the layout of the chunks,
+    /// and every chunk is checked to fit T once it is reached.
+    ///
     /// unsafety: make sure start is actually an initialized chunk
-    /// of the right type and only (recursively) next_hint-points to initialized chunks
-    /// and the Chunk<u8> need to actually be valid
-    /// Chunk<T> for each chunk of the list
-    pub unsafe fn new(data: *const [Chunk<u8>], start: usize) -> Self {
+    /// and only (recursively) next_hint-points to initialized chunks
+    ///
+    /// panics if T does not fit into a chunk, see Chunk::view.
+    pub unsafe fn new(data: *const [Chunk<u8>], start: Option<ChunkId<T>>) -> Self
+    where
+        T: ChunkPod,
+    {
+        Chunk::<u8>::check_pod::<T>();
         Self {
             data: data as *const _,
             current: start,
             phantom: PhantomData::default(),
         }
     }
+
+    /// iterates over the elements of the chunks instead.
+    pub fn elements(self) -> Iter<'a, T> {
+        Iter {
+            chunks: self,
+            current: [].iter(),
+        }
+    }
 }
 
 impl<'a, T> Iterator for Cursor<'a, T> {
-    type Item = (usize, &'a Chunk<T>);
-    fn next(&mut self) -> std::option::Option<<Self as std::iter::Iterator>::Item> {
-        if self.current == Link::<Chunk<u8>>::empty() {
-            None
-        } else {
-            // ok cause new guarantees validity
-            let data = unsafe { self.data.add(self.current) };
-            let data = unsafe { data.as_ref() }.unwrap();
-            let current = self.current;
-            self.current = data.next_hint;
-
-            Some((current, data))
-        }
+    type Item = (ChunkId<T>, &'a Chunk<T>);
+    fn next(&mut self) -> core::option::Option<<Self as core::iter::Iterator>::Item> {
+        let current = self.current?;
+        // ok cause new guarantees validity
+        let data = unsafe { self.data.add(current.pos()) };
+        let data = unsafe { data.as_ref() }.unwrap();
+        check_len(current, data);
+        self.current = data.next();
+
+        Some((current, data))
     }
 }
 
 pub struct CursorMut<'a, T> {
     data: *mut Chunk<T>,
-    current: usize,
+    current: Option<ChunkId<T>>,
     phantom: PhantomData<&'a mut T>,
 }
 
 impl<'a, T> CursorMut<'a, T> {
+    /// T and the chunks are checked like in Cursor::new.
+    ///
     /// unsafety: make sure start is actually an initialized chunk
-    /// of the right type and only (recursively) next_hint-points to initialized chunks
-    /// and the Chunk<u8> need to actually be valid
-    /// Chunk<T> for each chunk of the list
+    /// and only (recursively) next_hint-points to initialized chunks
     /// also only ever create one CursorMut from the same start.
     /// while a CursorMut exists don't create a Cursor.
     /// If you crate multiple CursorMut with the same or overlapping datas
     /// make sure that only disjunct chunks are linked.
     /// i.e. ensure rusts aliasing rules are satisfied.
-    pub unsafe fn new(data: *mut [Chunk<u8>], start: usize) -> Self {
+    pub unsafe fn new(data: *mut [Chunk<u8>], start: Option<ChunkId<T>>) -> Self
+    where
+        T: ChunkPod,
+    {
+        Chunk::<u8>::check_pod::<T>();
         Self {
             data: data as *mut _,
             current: start,
@@ -76,21 +95,153 @@ impl<'a, T> CursorMut<'a, T> {
             phantom: PhantomData::default(),
         }
     }
+
+    /// iterates over the elements of the chunks instead.
+    pub fn elements(self) -> IterMut<'a, T> {
+        IterMut {
+            chunks: self,
+            current: [].iter_mut(),
+        }
+    }
 }
 
 impl<'a, T> Iterator for CursorMut<'a, T> {
-    type Item = (usize, &'a mut Chunk<T>);
-    fn next(&mut self) -> std::option::Option<<Self as std::iter::Iterator>::Item> {
-        if self.current == Link::<Chunk<u8>>::empty() {
-            None
-        } else {
-            // ok cause new guarantees validity
-            let data = unsafe { self.data.add(self.current) };
-            let data = unsafe { data.as_mut() }.unwrap();
-            let current = self.current;
-            self.current = data.next_hint;
-
-            Some((current, data))
+    type Item = (ChunkId<T>, &'a mut Chunk<T>);
+    fn next(&mut self) -> core::option::Option<<Self as core::iter::Iterator>::Item> {
+        let current = self.current?;
+        // ok cause new guarantees validity
+        let data = unsafe { self.data.add(current.pos()) };
+        let data = unsafe { data.as_mut() }.unwrap();
+        check_len(current, data);
+        self.current = data.next();
+
+        Some((current, data))
+    }
+}
+
+/// Iterator over the elements of a list, chunk after chunk.
+///
+/// nth skips whole chunks using their len, without looking at their elements.
+/// The total length is not tracked, so this is not an ExactSizeIterator.
+pub struct Iter<'a, T> {
+    chunks: Cursor<'a, T>,
+    current: core::slice::Iter<'a, T>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> {
+        self.nth(0)
+    }
+
+    fn nth(&mut self, mut n: usize) -> Option<&'a T> {
+        while n >= self.current.len() {
+            n -= self.current.len();
+            self.current = match self.chunks.next() {
+                Some((_id, chunk)) => chunk.iter(),
+                None => {
+                    self.current = [].iter();
+                    return None;
+                }
+            };
+        }
+        self.current.nth(n)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.current.len();
+        match self.chunks.current {
+            Some(_) => (len, None),
+            None => (len, Some(len)),
         }
     }
 }
+
+impl<'a, T> FusedIterator for Iter<'a, T> {}
+
+/// Mutable iterator over the elements of a list, chunk after chunk.
+///
+/// nth skips whole chunks using their len, without looking at their elements.
+pub struct IterMut<'a, T> {
+    chunks: CursorMut<'a, T>,
+    current: core::slice::IterMut<'a, T>,
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = &'a mut T;
+    fn next(&mut self) -> Option<&'a mut T> {
+        self.nth(0)
+    }
+
+    fn nth(&mut self, mut n: usize) -> Option<&'a mut T> {
+        while n >= self.current.len() {
+            n -= self.current.len();
+            self.current = match self.chunks.next() {
+                Some((_id, chunk)) => chunk.iter_mut(),
+                None => {
+                    self.current = [].iter_mut();
+                    return None;
+                }
+            };
+        }
+        self.current.nth(n)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.current.len();
+        match self.chunks.current {
+            Some(_) => (len, None),
+            None => (len, Some(len)),
+        }
+    }
+}
+
+impl<'a, T> FusedIterator for IterMut<'a, T> {}
+
+#[test]
+fn iterators() {
+    use core::mem::MaybeUninit;
+
+    // 3 -> 0 -> 2 -> 1, with empty chunks in the middle and at the end
+    let order = [3, 0, 2, 1];
+    let values: [&[u64]; 4] = [&[0, 1], &[], &[2, 3, 4], &[]];
+    let mut base: Vec<MaybeUninit<Chunk<u8>>> = (0..4).map(|_| MaybeUninit::uninit()).collect();
+    for (i, pos) in order.iter().enumerate() {
+        let chunk = Chunk::initialize(&mut base[*pos])
+            .view_mut::<u64>()
+            .unwrap();
+        for v in values[i] {
+            chunk.push(*v);
+        }
+        chunk.set_next(order.get(i + 1).map(|next| ChunkId::new(*next)));
+    }
+    let data = base.as_mut_slice() as *mut [MaybeUninit<Chunk<u8>>] as *mut [Chunk<u8>];
+    let start = Some(ChunkId::new(3));
+
+    let cursor = unsafe { Cursor::<u64>::new(data, start) };
+    let ids: Vec<usize> = cursor.map(|(id, _chunk)| id.pos()).collect();
+    assert_eq!(ids, order);
+    assert!(cursor.elements().copied().eq(0..5));
+    let mut iter = cursor.elements();
+    assert_eq!(iter.size_hint(), (0, None));
+    assert_eq!(iter.nth(2), Some(&2));
+    assert_eq!(iter.size_hint(), (2, None));
+    assert_eq!(iter.nth(2), None);
+    assert_eq!(iter.size_hint(), (0, Some(0)));
+    assert_eq!(iter.next(), None);
+
+    let cursor = unsafe { CursorMut::<u64>::new(data, start) };
+    for v in cursor.elements() {
+        *v += 10;
+    }
+    let mut iter = unsafe { CursorMut::<u64>::new(data, start) }.elements();
+    assert_eq!(iter.size_hint(), (0, None));
+    assert_eq!(iter.nth(3), Some(&mut 13));
+    *iter.next().unwrap() = 0;
+    assert_eq!(iter.next(), None);
+    assert_eq!(iter.size_hint(), (0, Some(0)));
+    assert_eq!(iter.next(), None);
+
+    let cursor = unsafe { Cursor::<u64>::new(data, start) };
+    assert!(cursor.elements().copied().eq(vec![10, 11, 12, 13, 0]));
+}
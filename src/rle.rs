@@ -1,34 +1,64 @@
 //! this is not true rle, it basically only marks spans of used or unused
+use crate::base_chunk::ChunkId;
+use crate::base_chunk::ChunkPod;
+use crate::superblock::Error;
+use crate::superblock::LockedList;
 use crate::superblock::Superblock;
 type Chunk<T> = crate::base_chunk::Chunk<T, usize>;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(C)]
 pub struct Entry {
     start: u32,
     len: u32,
 }
 
+// two u32, no padding
+unsafe impl ChunkPod for Entry {}
+
 impl Entry {
+    pub fn new(start: u32, len: u32) -> Self {
+        Self { start, len }
+    }
+
+    /// first position of this span
+    pub fn start(&self) -> u32 {
+        self.start
+    }
+
+    /// number of positions in this span
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
     /// make sure you check self.len == 0 and remove after calling this.
     fn allocate(&mut self, count: u32) {
         self.start += count;
         self.len -= count;
     }
 
+    /// removes pos from this span.
+    /// pos needs to be inside the span.
+    ///
+    /// if pos is in the middle the span is split in two
+    /// self becomes the lower half, the upper half is returned.
+    ///
+    /// make sure you check self.len == 0 and remove after calling this.
     fn mark(&mut self, pos: u32) -> Option<Self> {
+        debug_assert!(self.start <= pos && pos < self.start + self.len);
         if pos == self.start {
             self.start += 1;
-            return None;
-        } else if pos == self.start + self.len {
             self.len -= 1;
-            return None;
+            None
+        } else if pos == self.start + self.len - 1 {
+            self.len -= 1;
+            None
         } else {
-            let mut other = self.clone();
-
-            self.len = self.start - pos;
-            other.len -= self.len;
-            other.len -= 1;
-            other.start = self.start + self.len + 1;
+            let other = Entry {
+                start: pos + 1,
+                len: self.start + self.len - (pos + 1),
+            };
+            self.len = pos - self.start;
 
             Some(other)
         }
@@ -39,37 +69,33 @@ type EntryChunk = Chunk<Entry>;
 
 impl EntryChunk {
     /// tries to mark a location as used.
+    /// returns Ok(false) if the location is not inside any span in this chunk.
     ///
     /// if that would cause a new entry to be added
     /// and if this chunk is full
     /// returns the new entry and the position it should have been inserted
     /// after.
-    pub fn mark(&mut self, pos: u32) -> Result<(), (usize, Entry)> {
-        let mut epos = None;
-        for (i, e) in self.iter_mut().enumerate() {
-            // maybe off by one, and it should be > instead of >=
-            if e.start + e.len >= pos {
-                epos = Some((i, e));
-                break;
-            }
-        }
-
-        let (epos, entry) = epos.unwrap();
+    pub fn mark(&mut self, pos: u32) -> Result<bool, (usize, Entry)> {
+        let epos = match self.iter().position(|e| e.start + e.len > pos) {
+            Some(epos) if self[epos].start <= pos => epos,
+            _ => return Ok(false),
+        };
 
-        let add = entry.mark(pos);
+        let add = self[epos].mark(pos);
 
         if let Some(add) = add {
             if let Err(e) = self.insert(epos + 1, add) {
                 return Err((epos, e));
             }
-        } else if entry.len == 0 {
+        } else if self[epos].len == 0 {
             self.remove(epos);
         }
 
-        Ok(())
+        Ok(true)
     }
 
     /// tries to free an entry (pos+len)
+    /// merges it with adjacent entries inside this chunk.
     ///
     /// if that would cause the chunk to overfill returns the new entry and the position it would
     /// have been inserted at
@@ -78,83 +104,382 @@ impl EntryChunk {
     pub fn unmark(&mut self, e: Entry) -> Option<(usize, Entry)> {
         let insert_pos = self
             .binary_search_by_key(&e.start, |e| e.start)
-            .unwrap_err();
+            .expect_err("double free");
 
         let pre_adj = insert_pos != 0 && {
-            let pre = self.get(insert_pos - 1).unwrap();
+            let pre = &self[insert_pos - 1];
+            assert!(pre.start + pre.len <= e.start, "double free");
             pre.start + pre.len == e.start
         };
+        let post_adj = insert_pos != self.len() && {
+            let post = &self[insert_pos];
+            assert!(e.start + e.len <= post.start, "double free");
+            e.start + e.len == post.start
+        };
 
-        // extend existing entry
-        if pre_adj {
-            self.get_mut(insert_pos - 1).unwrap().len += e.len;
-            None
-        } else {
-            if let Err(e) = self.insert(insert_pos, e) {
-                Some((insert_pos, e))
-            } else {
-                None
+        match (pre_adj, post_adj) {
+            // extend existing entry
+            (true, false) => self[insert_pos - 1].len += e.len,
+            (false, true) => {
+                let post = &mut self[insert_pos];
+                post.start = e.start;
+                post.len += e.len;
+            }
+            // close the gap between two entries
+            (true, true) => {
+                let post = self.remove(insert_pos).unwrap();
+                self[insert_pos - 1].len += e.len + post.len;
+            }
+            (false, false) => {
+                if let Err(e) = self.insert(insert_pos, e) {
+                    return Some((insert_pos, e));
+                }
             }
         }
+        None
     }
 }
 
+/// A list of free spans, stored in chunks of a Superblock.
+///
+/// Starts out with nothing free, use unmark to add free spans.
+/// The chunks holding the spans are taken from and returned to a FreeList,
+/// which can't be the list itself.
+/// Everything that needs a chunk returns Error::Exhausted once that FreeList is,
+/// without changing the list.
+/// Errors from returning an emptied chunk to the FreeList are returned after the change
+/// has been made.
 pub struct RleList<'s> {
-    start: &'s mut (usize, usize),
-    // i probably need some specialcasing in case this _is_ the freelist
-    freelist: usize,
-    list: &'s Superblock,
+    list: LockedList<'s, Entry>,
 }
 
 impl<'s> RleList<'s> {
     /// unsafe because you need to pass in valid start and freelist entries
     /// start is from locking the passed list on the correct index
     /// and freelist is the index of the freelist.
+    ///
+    /// panics if freelist is the entry start is from.
     pub unsafe fn new(
-        list: &'s Superblock,
+        superblock: &'s Superblock,
         start: &'s mut (usize, usize),
         freelist: usize,
     ) -> Self {
         Self {
-            start,
-            list,
-            freelist,
+            list: LockedList::new(superblock, start, freelist),
         }
     }
 
-    pub fn mark(&mut self, pos: u32) {
-        todo!()
+    /// inserts e at pos into chunk id, which is full.
+    /// nothing changes if there is no chunk to split into.
+    fn insert_split(&mut self, id: ChunkId<Entry>, pos: usize, e: Entry) -> Result<(), Error> {
+        let new = self.list.alloc_chunk()?;
+        // id is part of the list, new has just been allocated
+        unsafe { self.list.insert_split(id, pos, e, new) };
+        Ok(())
     }
 
-    pub fn unmark(&mut self, e: Entry) {
-        todo!()
+    /// marks pos as used.
+    /// returns false if pos was already used.
+    pub fn mark(&mut self, pos: u32) -> Result<bool, Error> {
+        let found = self.list.chunks().find(|(_id, chunk)| match chunk.last() {
+            Some(last) => last.start + last.len > pos,
+            None => false,
+        });
+        let (pre, id) = match found {
+            Some((id, _chunk)) => self.list.make_mut(id)?,
+            // past the last free span
+            None => return Ok(false),
+        };
+
+        let chunk = unsafe { self.list.chunk_mut(id) };
+        match chunk.mark(pos) {
+            Ok(marked) => {
+                if chunk.len() == 0 {
+                    self.list.unlink(pre, id)?;
+                }
+                Ok(marked)
+            }
+            Err((epos, e)) => {
+                if let Err(err) = self.insert_split(id, epos + 1, e) {
+                    // put the span back together, pos stays free
+                    chunk[epos].len = e.start + e.len - chunk[epos].start;
+                    return Err(err);
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    /// marks the span e as free.
+    ///
+    /// panics on a double-free.
+    pub fn unmark(&mut self, e: Entry) -> Result<(), Error> {
+        if e.len == 0 {
+            return Ok(());
+        }
+
+        // find the first chunk that reaches up to e, or the last chunk.
+        let mut id = None;
+        for (current, chunk) in self.list.chunks() {
+            id = Some(current);
+            match chunk.last() {
+                Some(last) if last.start + last.len >= e.start => break,
+                _ => {}
+            }
+        }
+        let id = match id {
+            Some(id) => self.list.make_mut(id)?.1,
+            None => {
+                // first span, need a chunk to put it in
+                let new = self.list.alloc_chunk()?;
+                // new has just been allocated
+                assert!(unsafe { self.list.push_front(new) }.push(e).is_none());
+                self.list.sync_start();
+                return Ok(());
+            }
+        };
+
+        let chunk = unsafe { self.list.chunk_mut(id) };
+        // e might belong at the end of this chunk while touching the start of the next one.
+        // that merge has to happen across chunks.
+        if chunk.has_next() && chunk.last().map_or(true, |last| last.start < e.start) {
+            let (_id, next_id) = self.list.make_mut(chunk.next().unwrap())?;
+            let next = unsafe { self.list.chunk_mut(next_id) };
+            let post = next.first_mut().unwrap();
+            assert!(e.start + e.len <= post.start, "double free");
+            if e.start + e.len == post.start {
+                let pre_adj = match chunk.last() {
+                    Some(last) => {
+                        assert!(last.start + last.len <= e.start, "double free");
+                        last.start + last.len == e.start
+                    }
+                    None => false,
+                };
+                if pre_adj {
+                    let post = next.remove(0).unwrap();
+                    chunk.last_mut().unwrap().len += e.len + post.len;
+                    if next.len() == 0 {
+                        self.list.unlink(Some(id), next_id)?;
+                    }
+                } else {
+                    post.start = e.start;
+                    post.len += e.len;
+                }
+                return Ok(());
+            }
+        }
+
+        match chunk.unmark(e) {
+            Some((pos, e)) => self.insert_split(id, pos, e),
+            None => Ok(()),
+        }
     }
 
     /// returns an entry. its len might be smaller than requested
     /// if no continious space could be found.
-    /// you can call again to satisfy your requests until you get an error,
+    /// you can call again to satisfy your requests until you get None,
     /// which signifies exhaustion.
-    pub fn alloc(&mut self, size: u32) -> Result<Entry, ()> {
-        /*
-                use crate::slicelist::CursorMut;
-                use crate::slicelist::IterExt;
-                //todo: need to make the cursor superblock-compatible
-                let iter = unsafe { CursorMut::<Entry>::from_byteslice(self.chunks, self.initial) };
-                let mut iter = iter.filter_map(|(c_id, chunk)| {
-                    let max = chunk
-                        .iter_mut()
-                        .enumerate()
-                        .max_by_key_with_cutoff(|(_, e)| e.len, count)?;
-                    let max = (max.1.len, max.0);
-                    Some((c_id, chunk, max))
-                });
-
-                let mut max = if let Some(e) = iter.next() {
-                    e
-                } else {
-                    return Err(());
-                };
-        */
-        todo!()
+    pub fn alloc(&mut self, size: u32) -> Result<Option<Entry>, Error> {
+        use crate::slicelist::IterExt;
+
+        // (id, in chunk position, len)
+        let mut best: Option<(ChunkId<Entry>, usize, u32)> = None;
+        for (id, chunk) in self.list.chunks() {
+            let max = chunk
+                .iter()
+                .enumerate()
+                .max_by_key_with_cutoff(|(_, e)| e.len, size);
+            if let Some((pos, e)) = max {
+                if best.map_or(true, |b| e.len > b.2) {
+                    best = Some((id, pos, e.len));
+                }
+                if e.len >= size {
+                    break;
+                }
+            }
+        }
+
+        let (id, pos, _) = match best {
+            Some(best) => best,
+            None => return Ok(None),
+        };
+        let (pre, id) = self.list.make_mut(id)?;
+        let chunk = unsafe { self.list.chunk_mut(id) };
+        let entry = &mut chunk[pos];
+        let to_alloc = size.min(entry.len);
+        let start = entry.start;
+        entry.allocate(to_alloc);
+        if entry.len == 0 {
+            chunk.remove(pos);
+            if chunk.len() == 0 {
+                self.list.unlink(pre, id)?;
+            }
+        }
+
+        Ok(Some(Entry {
+            start,
+            len: to_alloc,
+        }))
+    }
+}
+
+/// the entries of every chunk, in order.
+#[cfg(test)]
+fn spans(list: &RleList) -> Vec<Vec<Entry>> {
+    list.list
+        .chunks()
+        .map(|(_id, chunk)| chunk.to_vec())
+        .collect()
+}
+
+#[test]
+fn mark_unmark() {
+    let sb = Superblock::anonymous(64).unwrap();
+    let freelist = sb.header().freelist as usize;
+    let mut root = sb.lock(1).unwrap();
+    let mut list = unsafe { RleList::new(&sb, &mut root, freelist) };
+    assert_eq!(list.alloc(1).unwrap(), None);
+    assert!(!list.mark(0).unwrap());
+
+    // every other position is free, that does not fit into a single chunk
+    let n = 4000;
+    for i in (0..n).step_by(2) {
+        list.unmark(Entry::new(i, 1)).unwrap();
+    }
+    assert!(spans(&list).len() > 1);
+    for i in (0..n).step_by(2) {
+        assert!(!list.mark(i + 1).unwrap());
+    }
+
+    // fill the gaps, everything should merge back into a single span
+    for i in (1..n).step_by(2) {
+        list.unmark(Entry::new(i, 1)).unwrap();
+    }
+    assert_eq!(spans(&list), vec![vec![Entry::new(0, n)]]);
+
+    assert!(list.mark(10).unwrap());
+    assert!(!list.mark(10).unwrap());
+    assert!(list.mark(0).unwrap());
+    assert_eq!(
+        spans(&list),
+        vec![vec![Entry::new(1, 9), Entry::new(11, n - 11)]]
+    );
+
+    assert_eq!(list.alloc(5).unwrap(), Some(Entry::new(1, 5)));
+    assert_eq!(list.alloc(20).unwrap(), Some(Entry::new(11, 20)));
+    assert_eq!(list.alloc(4).unwrap(), Some(Entry::new(6, 4)));
+    assert_eq!(list.alloc(n).unwrap(), Some(Entry::new(31, n - 31)));
+    assert_eq!(list.alloc(1).unwrap(), None);
+    assert!(spans(&list).is_empty());
+}
+
+#[test]
+fn unmark_across_chunks() {
+    let sb = Superblock::anonymous(64).unwrap();
+    let freelist = sb.header().freelist as usize;
+    let mut root = sb.lock(1).unwrap();
+    let mut list = unsafe { RleList::new(&sb, &mut root, freelist) };
+    for i in (0..4000).step_by(2) {
+        list.unmark(Entry::new(i, 1)).unwrap();
+    }
+    let before = spans(&list);
+    assert!(before.len() > 2);
+
+    // bridges the last span of the first chunk and the first span of the second one
+    let last = *before[0].last().unwrap();
+    list.unmark(Entry::new(last.start + 1, 1)).unwrap();
+    let after = spans(&list);
+    assert_eq!(*after[0].last().unwrap(), Entry::new(last.start, 3));
+    assert_eq!(after[1][..], before[1][1..]);
+
+    // only touches the first span of the third chunk, that is merged within the third chunk
+    let last = *before[1].last().unwrap();
+    assert!(list.mark(last.start).unwrap());
+    list.unmark(Entry::new(last.start + 1, 1)).unwrap();
+    let after = spans(&list);
+    assert_eq!(after[1].last(), before[1].iter().rev().nth(1));
+    assert_eq!(after[2][0], Entry::new(last.start + 1, 2));
+    assert_eq!(after[2][1..], before[2][1..]);
+}
+
+#[test]
+fn mark_full_chunk() {
+    let sb = Superblock::anonymous(64).unwrap();
+    let freelist = sb.header().freelist as usize;
+    let mut root = sb.lock(1).unwrap();
+    let mut list = unsafe { RleList::new(&sb, &mut root, freelist) };
+    list.unmark(Entry::new(0, 3)).unwrap();
+    let cap = list.list.chunks().next().unwrap().1.capacity() as u32;
+    for i in 1..cap {
+        list.unmark(Entry::new(i * 4, 3)).unwrap();
+    }
+    assert_eq!(spans(&list).len(), 1);
+
+    // splitting a span needs one more entry than fits
+    assert!(list.mark(41).unwrap());
+    let spans = spans(&list);
+    assert_eq!(spans.len(), 2);
+    let entries: Vec<Entry> = spans.into_iter().flatten().collect();
+    assert_eq!(entries.len() as u32, cap + 1);
+    assert_eq!(entries[10..12], [Entry::new(40, 1), Entry::new(42, 1)]);
+    assert!(!list.mark(41).unwrap());
+}
+
+#[test]
+fn mark_exhausted() {
+    let sb = Superblock::anonymous(64).unwrap();
+    let freelist = sb.header().freelist as usize;
+    let mut root = sb.lock(1).unwrap();
+    let mut list = unsafe { RleList::new(&sb, &mut root, freelist) };
+    list.unmark(Entry::new(0, 3)).unwrap();
+    let cap = list.list.chunks().next().unwrap().1.capacity() as u32;
+    for i in 1..cap {
+        list.unmark(Entry::new(i * 4, 3)).unwrap();
+    }
+    while sb.alloc_chunks::<u8>(freelist, 1).unwrap().is_ok() {}
+
+    // splitting the span needs another chunk, the span stays as it was
+    let before = spans(&list);
+    match list.mark(41) {
+        Err(Error::Exhausted) => {}
+        other => panic!("expected the freelist to be exhausted, got {:?}", other),
+    }
+    assert_eq!(spans(&list), before);
+    // the ends of a span don't need another chunk
+    assert!(list.mark(40).unwrap());
+}
+
+#[test]
+#[should_panic(expected = "itself as its freelist")]
+fn own_freelist() {
+    let sb = Superblock::anonymous(64).unwrap();
+    let freelist = sb.header().freelist as usize;
+    let mut root = sb.lock(freelist).unwrap();
+    unsafe { RleList::new(&sb, &mut root, freelist) };
+}
+
+#[test]
+#[should_panic(expected = "double free")]
+fn double_free() {
+    let sb = Superblock::anonymous(64).unwrap();
+    let freelist = sb.header().freelist as usize;
+    let mut root = sb.lock(1).unwrap();
+    let mut list = unsafe { RleList::new(&sb, &mut root, freelist) };
+    list.unmark(Entry::new(10, 5)).unwrap();
+    list.unmark(Entry::new(12, 1)).unwrap();
+}
+
+#[test]
+#[should_panic(expected = "double free")]
+fn double_free_across_chunks() {
+    let sb = Superblock::anonymous(64).unwrap();
+    let freelist = sb.header().freelist as usize;
+    let mut root = sb.lock(1).unwrap();
+    let mut list = unsafe { RleList::new(&sb, &mut root, freelist) };
+    for i in (0..4000).step_by(2) {
+        list.unmark(Entry::new(i, 1)).unwrap();
     }
+    let first = spans(&list)[1][0];
+    // overlaps the first span of the second chunk
+    list.unmark(Entry::new(first.start - 1, 2)).unwrap();
 }
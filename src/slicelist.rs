@@ -1,115 +1,528 @@
-use crate::base_chunk::Link;
-type Chunk<T> = crate::base_chunk::Chunk<T, usize>;
-use std::mem::MaybeUninit;
+use crate::base_chunk::ChunkId;
+use crate::base_chunk::ChunkPod;
+use crate::base_chunk::ChunkSize;
+use crate::base_chunk::Size;
+use crate::base_chunk::DEFAULT_CHUNK_SIZE;
+type Chunk<T, const SIZE: usize = DEFAULT_CHUNK_SIZE> = crate::base_chunk::Chunk<T, usize, SIZE>;
+use core::iter::FusedIterator;
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
 
 #[derive(Clone, Copy)]
-pub struct Cursor<'a, T> {
-    data: &'a [MaybeUninit<Chunk<T>>],
-    current: usize,
+pub struct Cursor<'a, T, const SIZE: usize = DEFAULT_CHUNK_SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    data: *const MaybeUninit<Chunk<T, SIZE>>,
+    /// number of chunks behind data
+    len: usize,
+    current: Option<ChunkId<T>>,
+    phantom: PhantomData<&'a [MaybeUninit<Chunk<T, SIZE>>]>,
 }
 
-impl<'a, T> Cursor<'a, T> {
+impl<'a, T, const SIZE: usize> Cursor<'a, T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
     /// unsafety: make sure start is actually an initialzed chunk
     /// of the right type and only (recursively) next_hint-points to initialized chunks
-    pub unsafe fn new(data: &'a [MaybeUninit<Chunk<T>>], start: usize) -> Self {
+    pub unsafe fn new(data: &'a [MaybeUninit<Chunk<T, SIZE>>], start: Option<ChunkId<T>>) -> Self {
         Self {
-            data,
+            data: data.as_ptr(),
+            len: data.len(),
             current: start,
+            phantom: PhantomData,
         }
     }
 
-    /// unsafety: everything new states, and the Chunk<u8> need to actually be valid
-    /// Chunk<T> for each chunk of the list
-    pub unsafe fn from_byteslice(data: &'a [MaybeUninit<Chunk<u8>>], start: usize) -> Self {
-        let data = (data as *const [MaybeUninit<Chunk<u8>>] as *const [MaybeUninit<Chunk<T>>])
-            .as_ref()
-            .unwrap();
+    /// like new, but on chunks of bytes. like Chunk::view, T is checked to have the layout
+    /// of the chunks, and every chunk is checked to fit T once it is reached.
+    ///
+    /// unsafety: everything new states, except the chunks only need to be initialized
+    /// Chunk<u8>.
+    ///
+    /// panics if T does not fit into a chunk, see Chunk::view.
+    pub unsafe fn from_byteslice(
+        data: &'a [MaybeUninit<Chunk<u8, SIZE>>],
+        start: Option<ChunkId<T>>,
+    ) -> Self
+    where
+        T: ChunkPod,
+    {
+        Self::from_raw(data.as_ptr(), data.len(), start)
+    }
+
+    /// like from_byteslice, but on the len chunks starting at data.
+    /// only the chunks of the list are ever referenced,
+    /// so the other chunks can be in use by someone else in the meantime.
+    ///
+    /// unsafety: everything from_byteslice states, and the chunks need to stay valid for 'a.
+    pub unsafe fn from_raw(
+        data: *const MaybeUninit<Chunk<u8, SIZE>>,
+        len: usize,
+        start: Option<ChunkId<T>>,
+    ) -> Self
+    where
+        T: ChunkPod,
+    {
+        Chunk::<u8, SIZE>::check_pod::<T>();
         Self {
-            data,
+            data: data as *const MaybeUninit<Chunk<T, SIZE>>,
+            len,
             current: start,
+            phantom: PhantomData,
+        }
+    }
+
+    /// iterates over the elements of the chunks instead.
+    pub fn elements(self) -> Iter<'a, T, SIZE> {
+        Iter {
+            chunks: self,
+            current: [].iter(),
         }
     }
 }
 
-impl<'a, T> Iterator for Cursor<'a, T> {
-    type Item = (usize, &'a Chunk<T>);
-    fn next(&mut self) -> std::option::Option<<Self as std::iter::Iterator>::Item> {
-        if self.current == Link::<Chunk<u8>>::empty() {
-            None
-        } else {
-            let data = &self.data[self.current];
-            let data = unsafe { data.get_ref() };
-            let current = self.current;
-            self.current = data.next_hint;
+impl<'a, T, const SIZE: usize> Iterator for Cursor<'a, T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    type Item = (ChunkId<T>, &'a Chunk<T, SIZE>);
+    fn next(&mut self) -> core::option::Option<<Self as core::iter::Iterator>::Item> {
+        let current = self.current?;
+        let data = chunk_ptr(self.data as *mut _, self.len, current);
+        // new guarantees the chunk is initialized and nobody is writing to it
+        let data = unsafe { data.as_ref().unwrap().get_ref() };
+        check_len(current, data);
+        self.current = data.next();
 
-            Some((current, &data))
-        }
+        Some((current, data))
     }
 }
 
-pub struct CursorMut<'a, T> {
-    data: &'a mut [MaybeUninit<Chunk<T>>],
-    current: usize,
+pub struct CursorMut<'a, T, const SIZE: usize = DEFAULT_CHUNK_SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    data: *mut MaybeUninit<Chunk<T, SIZE>>,
+    /// number of chunks behind data
+    len: usize,
+    current: Option<ChunkId<T>>,
+    phantom: PhantomData<&'a mut [MaybeUninit<Chunk<T, SIZE>>]>,
 }
 
-impl<'a, T> CursorMut<'a, T> {
+impl<'a, T, const SIZE: usize> CursorMut<'a, T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
     /// unsafety: make sure start is actually an initialzed chunk
     /// of the right type and only (recursively) next_hint-points to initialized chunks
     /// and never has any loops
     /// also never make changes that invalidate the list, specifically don't change
     /// next_hint to an invalid value
-    pub unsafe fn new(data: &'a mut [MaybeUninit<Chunk<T>>], start: usize) -> Self {
+    pub unsafe fn new(
+        data: &'a mut [MaybeUninit<Chunk<T, SIZE>>],
+        start: Option<ChunkId<T>>,
+    ) -> Self {
         Self {
-            data,
+            data: data.as_mut_ptr(),
+            len: data.len(),
             current: start,
+            phantom: PhantomData,
         }
     }
 
-    /// unsafety: everything new states, and the Chunk<u8> need to actually be valid
-    /// Chunk<T> for each chunk of the list
-    pub unsafe fn from_byteslice(data: &'a mut [MaybeUninit<Chunk<u8>>], start: usize) -> Self {
-        let data = (data as *mut [MaybeUninit<Chunk<u8>>] as *mut [MaybeUninit<Chunk<T>>])
-            .as_mut()
-            .unwrap();
+    /// like new, but on chunks of bytes, see Cursor::from_byteslice.
+    ///
+    /// unsafety: everything new states, except the chunks only need to be initialized
+    /// Chunk<u8>.
+    pub unsafe fn from_byteslice(
+        data: &'a mut [MaybeUninit<Chunk<u8, SIZE>>],
+        start: Option<ChunkId<T>>,
+    ) -> Self
+    where
+        T: ChunkPod,
+    {
+        Self::from_raw(data.as_mut_ptr(), data.len(), start)
+    }
+
+    /// like from_byteslice, but on the len chunks starting at data, see Cursor::from_raw.
+    ///
+    /// unsafety: everything from_byteslice states, and the chunks need to stay valid for 'a.
+    pub unsafe fn from_raw(
+        data: *mut MaybeUninit<Chunk<u8, SIZE>>,
+        len: usize,
+        start: Option<ChunkId<T>>,
+    ) -> Self
+    where
+        T: ChunkPod,
+    {
+        Chunk::<u8, SIZE>::check_pod::<T>();
         Self {
-            data,
+            data: data as *mut MaybeUninit<Chunk<T, SIZE>>,
+            len,
             current: start,
+            phantom: PhantomData,
         }
     }
 
     /// Creates a "clone" of this Cursor, allowing you to move forward
     /// with the return value of this function
     /// and then snap back to where you called it.
-    pub fn reborrow<'b>(&'a mut self) -> CursorMut<'b, T>
+    pub fn reborrow<'b>(&'a mut self) -> CursorMut<'b, T, SIZE>
     where
         'a: 'b,
     {
         CursorMut {
             current: self.current,
-            data: &mut *self.data,
+            data: self.data,
+            len: self.len,
+            phantom: PhantomData,
+        }
+    }
+
+    /// iterates over the elements of the chunks instead.
+    pub fn elements(self) -> IterMut<'a, T, SIZE> {
+        IterMut {
+            chunks: self,
+            current: [].iter_mut(),
+        }
+    }
+}
+
+impl<'a, T, const SIZE: usize> Iterator for CursorMut<'a, T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    type Item = (ChunkId<T>, &'a mut Chunk<T, SIZE>);
+    fn next(&mut self) -> core::option::Option<<Self as core::iter::Iterator>::Item> {
+        let current = self.current?;
+        let data = chunk_ptr(self.data, self.len, current);
+        // we only ever hand out different chunks, as guaranteed by the unsafe new function
+        let data = unsafe { data.as_mut().unwrap().get_mut() };
+        check_len(current, data);
+        self.current = data.next();
+
+        Some((current, data))
+    }
+}
+
+/// Iterator over the elements of a list, chunk after chunk.
+///
+/// nth skips whole chunks using their len, without looking at their elements.
+/// The total length is not tracked, so this is not an ExactSizeIterator.
+pub struct Iter<'a, T, const SIZE: usize = DEFAULT_CHUNK_SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    chunks: Cursor<'a, T, SIZE>,
+    current: core::slice::Iter<'a, T>,
+}
+
+impl<'a, T, const SIZE: usize> Iterator for Iter<'a, T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    type Item = &'a T;
+    fn next(&mut self) -> Option<&'a T> {
+        self.nth(0)
+    }
+
+    fn nth(&mut self, mut n: usize) -> Option<&'a T> {
+        while n >= self.current.len() {
+            n -= self.current.len();
+            self.current = match self.chunks.next() {
+                Some((_id, chunk)) => chunk.iter(),
+                None => {
+                    self.current = [].iter();
+                    return None;
+                }
+            };
+        }
+        self.current.nth(n)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.current.len();
+        match self.chunks.current {
+            Some(_) => (len, None),
+            None => (len, Some(len)),
         }
     }
 }
 
-impl<'a, T> Iterator for CursorMut<'a, T> {
-    type Item = (usize, &'a mut Chunk<T>);
-    fn next(&mut self) -> std::option::Option<<Self as std::iter::Iterator>::Item> {
-        if self.current == Link::<Chunk<u8>>::empty() {
-            None
+impl<'a, T, const SIZE: usize> FusedIterator for Iter<'a, T, SIZE> where Size<SIZE>: ChunkSize {}
+
+/// Mutable iterator over the elements of a list, chunk after chunk.
+///
+/// nth skips whole chunks using their len, without looking at their elements.
+pub struct IterMut<'a, T, const SIZE: usize = DEFAULT_CHUNK_SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    chunks: CursorMut<'a, T, SIZE>,
+    current: core::slice::IterMut<'a, T>,
+}
+
+impl<'a, T, const SIZE: usize> Iterator for IterMut<'a, T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    type Item = &'a mut T;
+    fn next(&mut self) -> Option<&'a mut T> {
+        self.nth(0)
+    }
+
+    fn nth(&mut self, mut n: usize) -> Option<&'a mut T> {
+        while n >= self.current.len() {
+            n -= self.current.len();
+            self.current = match self.chunks.next() {
+                Some((_id, chunk)) => chunk.iter_mut(),
+                None => {
+                    self.current = [].iter_mut();
+                    return None;
+                }
+            };
+        }
+        self.current.nth(n)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.current.len();
+        match self.chunks.current {
+            Some(_) => (len, None),
+            None => (len, Some(len)),
+        }
+    }
+}
+
+impl<'a, T, const SIZE: usize> FusedIterator for IterMut<'a, T, SIZE> where Size<SIZE>: ChunkSize {}
+
+/// A singly-linked list of chunks stored in a slice,
+/// the chunks link to each other by their index in the slice.
+///
+/// This only takes care of linking, splitting and unlinking chunks.
+/// Where new chunks come from and what happens to unlinked chunks is up to the user,
+/// chunks that are not part of the list are never touched.
+pub struct SliceList<'a, T, const SIZE: usize = DEFAULT_CHUNK_SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    data: *mut MaybeUninit<Chunk<T, SIZE>>,
+    /// number of chunks behind data
+    len: usize,
+    start: Option<ChunkId<T>>,
+    phantom: PhantomData<&'a mut [MaybeUninit<Chunk<T, SIZE>>]>,
+}
+
+impl<'a, T, const SIZE: usize> SliceList<'a, T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    /// creates a list consisting of a single, empty chunk at start.
+    /// only start is touched.
+    ///
+    /// panics if T does not fit into a chunk, see Chunk::view.
+    pub fn new(data: &'a mut [MaybeUninit<Chunk<u8, SIZE>>], start: ChunkId<T>) -> Self
+    where
+        T: ChunkPod,
+    {
+        // same layout, and whatever T leaves in the chunks can be read as bytes again.
+        let mut list = unsafe { Self::from_raw(data.as_mut_ptr(), data.len(), None) };
+        // we have exclusive access to all of data
+        Chunk::initialize(unsafe { chunk_ptr(list.data, list.len, start).as_mut().unwrap() });
+        list.start = Some(start);
+        list
+    }
+
+    /// reads a previously created list.
+    ///
+    /// unsafety: same as CursorMut::from_byteslice.
+    pub unsafe fn from_byteslice(
+        data: &'a mut [MaybeUninit<Chunk<u8, SIZE>>],
+        start: Option<ChunkId<T>>,
+    ) -> Self
+    where
+        T: ChunkPod,
+    {
+        Self::from_raw(data.as_mut_ptr(), data.len(), start)
+    }
+
+    /// reads a previously created list from the len chunks starting at data,
+    /// see Cursor::from_raw.
+    ///
+    /// unsafety: same as CursorMut::from_raw.
+    pub unsafe fn from_raw(
+        data: *mut MaybeUninit<Chunk<u8, SIZE>>,
+        len: usize,
+        start: Option<ChunkId<T>>,
+    ) -> Self
+    where
+        T: ChunkPod,
+    {
+        Chunk::<u8, SIZE>::check_pod::<T>();
+        Self {
+            data: data as *mut MaybeUninit<Chunk<T, SIZE>>,
+            len,
+            start,
+            phantom: PhantomData,
+        }
+    }
+
+    /// the first chunk of this list, None once every chunk has been unlinked.
+    /// this changes if the first chunk is unlinked.
+    pub fn start(&self) -> Option<ChunkId<T>> {
+        self.start
+    }
+
+    pub fn chunks(&self) -> Cursor<'_, T, SIZE> {
+        // the list is always in a consistent state
+        Cursor {
+            data: self.data,
+            len: self.len,
+            current: self.start,
+            phantom: PhantomData,
+        }
+    }
+
+    pub fn chunks_mut(&mut self) -> CursorMut<'_, T, SIZE> {
+        // the list is always in a consistent state
+        CursorMut {
+            data: self.data,
+            len: self.len,
+            current: self.start,
+            phantom: PhantomData,
+        }
+    }
+
+    /// iterates over all elements, chunk after chunk.
+    pub fn iter(&self) -> Iter<'_, T, SIZE> {
+        self.chunks().elements()
+    }
+
+    /// iterates mutably over all elements, chunk after chunk.
+    pub fn iter_mut(&mut self) -> IterMut<'_, T, SIZE> {
+        self.chunks_mut().elements()
+    }
+
+    /// unsafety: id needs to be part of this list.
+    pub unsafe fn get(&self, id: ChunkId<T>) -> &Chunk<T, SIZE> {
+        let chunk = chunk_ptr(self.data, self.len, id)
+            .as_ref()
+            .unwrap()
+            .get_ref();
+        check_len(id, chunk);
+        chunk
+    }
+
+    /// unsafety: id needs to be part of this list.
+    pub unsafe fn get_mut(&mut self, id: ChunkId<T>) -> &mut Chunk<T, SIZE> {
+        let chunk = chunk_ptr(self.data, self.len, id)
+            .as_mut()
+            .unwrap()
+            .get_mut();
+        check_len(id, chunk);
+        chunk
+    }
+
+    /// makes new the first chunk of the list, initialized and empty.
+    ///
+    /// unsafety: new must not be part of this list, and nobody else can be using it.
+    /// new is overwritten, not dropped.
+    pub unsafe fn push_front(&mut self, new: ChunkId<T>) -> &mut Chunk<T, SIZE> {
+        let chunk = Chunk::initialize(chunk_ptr(self.data, self.len, new).as_mut().unwrap());
+        chunk.set_next(self.start);
+        self.start = Some(new);
+        chunk
+    }
+
+    /// moves the back half of chunk id into the chunk new,
+    /// which is linked in right behind it.
+    /// returns where the chunk was split, i.e. how many elements stayed in id.
+    ///
+    /// unsafety: id needs to be part of this list,
+    /// new must not be, and nobody else can be using it.
+    /// new is overwritten, not dropped.
+    pub unsafe fn split(&mut self, id: ChunkId<T>, new: ChunkId<T>) -> usize {
+        assert_ne!(id, new);
+        // id and new are different, so this is not aliasing
+        let other = chunk_ptr(self.data, self.len, new).as_mut().unwrap();
+        let chunk = self.get_mut(id);
+        let mid = chunk.len() / 2;
+        chunk.split_usize(mid, other, new);
+        mid
+    }
+
+    /// inserts v at pos into chunk id, after splitting it in half into new.
+    /// use this once inserting into the chunk itself failed.
+    ///
+    /// unsafety: same as split.
+    pub unsafe fn insert_split(&mut self, id: ChunkId<T>, pos: usize, v: T, new: ChunkId<T>) {
+        let mid = self.split(id, new);
+        let res = if pos <= mid {
+            self.get_mut(id).insert(pos, v)
         } else {
-            let data = &mut self.data[self.current];
-            let data = unsafe { data.get_mut() };
-            // extending lifetime here, should be safe because we only ever access different spots
-            // in the slice, as guaranteed by the unsafe new function
-            let data: &mut Chunk<T> = unsafe { (data as *mut Chunk<T>).as_mut().unwrap() };
-            let current = self.current;
-            self.current = data.next_hint;
+            self.get_mut(new).insert(pos - mid, v)
+        };
+        // both halves have space after the split
+        assert!(res.is_ok());
+    }
 
-            Some((current, data))
+    /// removes chunk id, which follows pre, from the list and drops it.
+    /// if id is the first chunk the next chunk becomes the first one.
+    /// afterwards id is not used by the list any more.
+    ///
+    /// unsafety: id and pre need to be part of this list.
+    pub unsafe fn unlink(&mut self, pre: Option<ChunkId<T>>, id: ChunkId<T>) {
+        let chunk = self.get_mut(id);
+        let next = chunk.next();
+        core::ptr::drop_in_place(chunk as *mut Chunk<T, SIZE>);
+        match pre {
+            Some(pre) => {
+                let pre = self.get_mut(pre);
+                debug_assert_eq!(pre.next(), Some(id));
+                pre.set_next(next);
+            }
+            None => {
+                debug_assert_eq!(self.start, Some(id));
+                self.start = next;
+            }
         }
     }
 }
 
+/// chunk id of the len chunks starting at data.
+///
+/// panics if id is out of bounds.
+fn chunk_ptr<T, const SIZE: usize>(
+    data: *mut MaybeUninit<Chunk<T, SIZE>>,
+    len: usize,
+    id: ChunkId<T>,
+) -> *mut MaybeUninit<Chunk<T, SIZE>>
+where
+    Size<SIZE>: ChunkSize,
+{
+    assert!(id.pos() < len, "chunk {} is out of bounds", id.pos());
+    // in bounds, so still inside of the same allocation
+    unsafe { data.add(id.pos()) }
+}
+
+/// panics if chunk id claims to hold more elements than fit into it.
+/// chunks read from bytes have only been checked to have the layout of a Chunk<T>,
+/// this is the rest of what Chunk::view checks.
+pub(crate) fn check_len<T, const SIZE: usize>(id: ChunkId<T>, chunk: &Chunk<T, SIZE>)
+where
+    Size<SIZE>: ChunkSize,
+{
+    assert!(
+        chunk.len() <= chunk.capacity(),
+        "chunk {} holds {} elements, only {} fit",
+        id.pos(),
+        chunk.len(),
+        chunk.capacity()
+    );
+}
+
 pub trait IterExt: Iterator {
     /// if the iterator contains items >= cutoff: returns the first of those
     /// if all items in the iterator are < cutoff: behaves like .max_by_key()
@@ -159,3 +572,55 @@ fn max_cutoff() {
     let b = a.iter().max_by_key_with_cutoff(|e| **e, 3);
     assert_eq!(b, None);
 }
+
+#[test]
+fn slice_list() {
+    let n_chunks = 4;
+    let mut base = Vec::with_capacity(n_chunks);
+    unsafe { base.set_len(n_chunks) };
+    let (first, second) = (ChunkId::new(2), ChunkId::new(0));
+    let mut list = SliceList::<u64>::new(&mut base, first);
+    let cap = unsafe { list.get(first) }.capacity();
+    for i in 0..cap as u64 {
+        assert_eq!(unsafe { list.get_mut(first) }.push(i * 2), None);
+    }
+    assert!(unsafe { list.get_mut(first) }.insert(0, 1).is_err());
+
+    // the back half ends up in chunk 0, which is linked in behind 2
+    unsafe { list.insert_split(first, cap - 1, 1, second) };
+    let ids: Vec<_> = list.chunks().map(|(id, _chunk)| id.pos()).collect();
+    assert_eq!(ids, vec![2, 0]);
+    assert_eq!(unsafe { list.get(first) }.len(), cap / 2);
+    assert_eq!(unsafe { list.get(second) }[cap - 1 - cap / 2], 1);
+    assert_eq!(list.iter().count(), cap + 1);
+    assert_eq!(list.iter().nth(cap / 2), Some(&((cap / 2) as u64 * 2)));
+    assert_eq!(list.iter().nth(cap - 1), Some(&1));
+    let mut iter = list.iter_mut();
+    assert_eq!(iter.nth(cap + 1), None);
+    assert_eq!(iter.next(), None);
+
+    unsafe { list.unlink(None, first) };
+    assert_eq!(list.start(), Some(second));
+    let ids: Vec<_> = list.chunks_mut().map(|(id, _chunk)| id).collect();
+    assert_eq!(ids, vec![second]);
+    unsafe { list.unlink(None, second) };
+    assert_eq!(list.start(), None);
+    assert!(list.chunks().next().is_none());
+    assert_eq!(list.iter().size_hint(), (0, Some(0)));
+
+    unsafe { list.push_front(second) }.push(3);
+    unsafe { list.push_front(first) }.push(2);
+    assert!(list.iter().copied().eq(vec![2, 3]));
+}
+
+#[test]
+#[should_panic]
+fn byteslice_len() {
+    let mut base = Vec::with_capacity(1);
+    base.push(MaybeUninit::uninit());
+    let bytes: &mut Chunk<u8> = Chunk::initialize(&mut base[0]);
+    while bytes.push(0).is_none() {}
+    // a full chunk of bytes claims more elements than fit as u64
+    let mut cursor = unsafe { Cursor::<u64>::from_byteslice(&base, Some(ChunkId::new(0))) };
+    cursor.next();
+}
@@ -0,0 +1,310 @@
+//! Copy-on-write snapshots of the lists in a Superblock.
+//!
+//! A snapshot remembers the root of a list and marks every chunk of it as frozen.
+//! Writers call SuperblockGuard::make_mut before changing a chunk,
+//! which copies frozen chunks, and every frozen chunk in front of them, into fresh chunks
+//! and links those in instead. The snapshot keeps seeing the originals.
+//! Chunks behind the changed one are still shared between the list and the snapshot.
+//! SuperblockGuard::cursor_mut, RleList and SortedList do this on their own.
+//!
+//! Frozen chunks that are no longer part of the list are freed once the last snapshot
+//! seeing them is dropped, see Superblock::release_chunk.
+//!
+//! Snapshots only live in memory, they don't survive closing the Superblock.
+use crate::base_chunk::ChunkId;
+use crate::base_chunk::ChunkPod;
+use crate::base_chunk::ChunkSize;
+use crate::base_chunk::Size;
+use crate::base_chunk::DEFAULT_CHUNK_SIZE;
+use crate::slicelist::Cursor;
+use crate::superblock::Error;
+use crate::superblock::Superblock;
+type Chunk<T, const SIZE: usize = DEFAULT_CHUNK_SIZE> = crate::base_chunk::Chunk<T, usize, SIZE>;
+
+/// bookkeeping for a chunk that is seen by snapshots.
+#[derive(Debug, Default)]
+pub(crate) struct Frozen {
+    /// number of snapshots seeing the chunk
+    snapshots: usize,
+    /// the freelist the chunk goes back to once no snapshot sees it any more,
+    /// if it has been released in the meantime.
+    pub(crate) release_to: Option<usize>,
+}
+
+/// A frozen, read-only view of a list, see the module docs.
+///
+/// The frozen chunks are unfrozen when this is dropped.
+pub struct Snapshot<'s, const SIZE: usize = DEFAULT_CHUNK_SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    superblock: &'s Superblock<SIZE>,
+    root: (usize, usize),
+    chunks: Vec<usize>,
+}
+
+impl<'s, const SIZE: usize> Snapshot<'s, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    pub(crate) fn new(superblock: &'s Superblock<SIZE>, pos: usize) -> Result<Self, Error> {
+        // nobody can change the list while we are freezing it
+        let guard = superblock.read(pos);
+        let root = *guard;
+        // next_hint is in the same spot for every kind of chunk
+        let chunks: Vec<usize> = unsafe { guard.cursor::<u8>() }?
+            .map(|(id, _chunk)| id.pos())
+            .collect();
+        let mut frozen = superblock.frozen.lock().unwrap();
+        for id in &chunks {
+            frozen.entry(*id).or_default().snapshots += 1;
+        }
+        Ok(Self {
+            superblock,
+            root,
+            chunks,
+        })
+    }
+
+    /// the root of the list at the time the snapshot was taken.
+    pub fn root(&self) -> (usize, usize) {
+        self.root
+    }
+
+    /// walks the list as it was when the snapshot was taken.
+    /// the chunks are checked to fit T, see Cursor::from_byteslice.
+    ///
+    /// every chunk is loaded first, see Superblock::load.
+    /// returns Error::Corrupt for the first one that does not match its checksum.
+    ///
+    /// safety: nobody can be writing to the chunks of the list in place,
+    /// which holds as long as changes go through SuperblockGuard::make_mut.
+    pub unsafe fn cursor<T: ChunkPod>(&self) -> Result<Cursor<'_, T, SIZE>, Error> {
+        let start = ChunkId::from_link(self.root.0);
+        self.superblock.load_list(start)?;
+        // frozen chunks are only ever read
+        let (base, len) = (self.superblock.base(), self.superblock.len());
+        Ok(Cursor::from_raw(base, len, start))
+    }
+}
+
+impl<'s, const SIZE: usize> Drop for Snapshot<'s, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    fn drop(&mut self) {
+        let mut release = Vec::new();
+        {
+            let mut frozen = self.superblock.frozen.lock().unwrap();
+            for id in &self.chunks {
+                let f = frozen.get_mut(id).unwrap();
+                f.snapshots -= 1;
+                if f.snapshots == 0 {
+                    if let Some(freelist) = frozen.remove(id).unwrap().release_to {
+                        release.push((freelist, *id));
+                    }
+                }
+            }
+        }
+        // the chunks are not part of any list and not seen by anyone any more.
+        // errors can't be reported from here, the chunks stay leaked then, see Superblock::check.
+        for (freelist, id) in release {
+            let _ = unsafe {
+                self.superblock
+                    .free_chunks(freelist, ChunkId::<u8>::new(id), 1)
+            };
+        }
+    }
+}
+
+/// see SuperblockGuard::make_mut, root is the guarded root.
+pub(crate) unsafe fn make_mut<T, const SIZE: usize>(
+    superblock: &Superblock<SIZE>,
+    root: &mut (usize, usize),
+    freelist: usize,
+    id: ChunkId<T>,
+) -> Result<ChunkId<T>, Error>
+where
+    Size<SIZE>: ChunkSize,
+{
+    superblock.assert_other_freelist(freelist, root);
+    // held for the whole walk, so no snapshot can be taken or dropped in the middle of it
+    let mut frozen = superblock.frozen.lock().unwrap();
+    if frozen.is_empty() {
+        return Ok(id);
+    }
+
+    let mut pre: Option<ChunkId<T>> = None;
+    let mut current = ChunkId::from_link(root.0);
+    loop {
+        let current_id = current.expect("make_mut called on a chunk that is not part of the list");
+        let writable = match frozen.get_mut(&current_id.pos()) {
+            Some(f) => {
+                let new = match superblock.alloc_chunks(freelist, 1)? {
+                    Ok(new) => new,
+                    Err(_) => return Err(Error::Exhausted),
+                };
+                // new has just been allocated, current is only read
+                superblock.copy_chunk(current_id.pos(), new.pos());
+                match pre {
+                    Some(pre) => superblock.get_mut(pre).get_mut().set_next(Some(new)),
+                    None => root.0 = new.pos(),
+                }
+                // the list does not use current any more, the snapshots do.
+                // it is freed once the last of them is dropped, like in Superblock::release_chunk.
+                f.release_to = Some(freelist);
+                new
+            }
+            None => current_id,
+        };
+        if current_id == id {
+            return Ok(writable);
+        }
+        pre = Some(writable);
+        current = superblock.get(writable).get_ref().next();
+    }
+}
+
+#[test]
+fn copy_on_write() {
+    fn values(cursor: Cursor<u64>) -> Vec<u64> {
+        cursor.elements().copied().collect()
+    }
+
+    let s: Superblock = Superblock::anonymous(64).unwrap();
+    let freelist = s.header().freelist as usize;
+
+    // a list of three chunks holding one value each
+    let mut guard = s.write(1);
+    let ids: Vec<ChunkId<u64>> = (0..3)
+        .map(|_| s.alloc_chunks(freelist, 1).unwrap().unwrap())
+        .collect();
+    for (i, id) in ids.iter().enumerate() {
+        let chunk = Chunk::initialize(unsafe { s.get_mut(*id) });
+        chunk.push(i as u64);
+        if let Some(next) = ids.get(i + 1) {
+            chunk.set_next(Some(*next));
+        }
+    }
+    guard.set_start(Some(ids[0]));
+    // nothing to copy without snapshots
+    assert_eq!(unsafe { guard.make_mut(freelist, ids[1]) }.unwrap(), ids[1]);
+    drop(guard);
+
+    let snapshot = s.snapshot(1).unwrap();
+    let mut guard = s.write(1);
+    // the first two chunks are copied, the last one is still shared
+    let new = unsafe { guard.make_mut(freelist, ids[1]) }.unwrap();
+    assert_ne!(new, ids[1]);
+    let chunk = unsafe { s.get_mut(new).get_mut() };
+    chunk[0] = 10;
+    // copying again is not necessary
+    assert_eq!(unsafe { guard.make_mut(freelist, new) }.unwrap(), new);
+    drop(guard);
+
+    let live: Vec<ChunkId<u64>> = unsafe { s.read(1).cursor::<u64>() }
+        .unwrap()
+        .map(|(id, _chunk)| id)
+        .collect();
+    assert_eq!(live.len(), 3);
+    assert_ne!(live[0], ids[0]);
+    assert_eq!(live[1], new);
+    assert_eq!(live[2], ids[2]);
+    assert_eq!(
+        values(unsafe { s.read(1).cursor() }.unwrap()),
+        vec![0, 10, 2]
+    );
+    assert_eq!(values(unsafe { snapshot.cursor() }.unwrap()), vec![0, 1, 2]);
+    assert_eq!(snapshot.root().0, ids[0].pos());
+
+    // cursor_mut copies the rest of the list
+    let mut guard = s.write(1);
+    for v in unsafe { guard.cursor_mut::<u64>(freelist) }
+        .unwrap()
+        .elements()
+    {
+        *v += 100;
+    }
+    drop(guard);
+    assert_eq!(
+        values(unsafe { s.read(1).cursor() }.unwrap()),
+        vec![100, 110, 102]
+    );
+    assert_eq!(values(unsafe { snapshot.cursor() }.unwrap()), vec![0, 1, 2]);
+
+    // the originals are only freed once the snapshot is gone
+    let other: ChunkId<u64> = s.alloc_chunks(freelist, 1).unwrap().unwrap();
+    assert!(!ids.contains(&other));
+    unsafe { s.free_chunks(freelist, other, 1) }.unwrap();
+    drop(snapshot);
+    assert_eq!(s.alloc_chunks(freelist, 2).unwrap(), Ok(ids[0]));
+}
+
+#[test]
+fn exhausted() {
+    let s: Superblock = Superblock::anonymous(64).unwrap();
+    let freelist = s.header().freelist as usize;
+    let mut guard = s.write(1);
+    let id: ChunkId<u64> = s.alloc_chunks(freelist, 1).unwrap().unwrap();
+    Chunk::initialize(unsafe { s.get_mut(id) }).push(1);
+    guard.set_start(Some(id));
+    drop(guard);
+
+    let snapshot = s.snapshot(1).unwrap();
+    while s.alloc_chunks::<u8>(freelist, 1).unwrap().is_ok() {}
+    let mut guard = s.write(1);
+    match unsafe { guard.make_mut(freelist, id) } {
+        Err(Error::Exhausted) => {}
+        other => panic!("expected the freelist to be exhausted, got {:?}", other),
+    }
+    assert!(unsafe { guard.cursor_mut::<u64>(freelist) }.is_err());
+    // nothing has been copied
+    assert_eq!(guard.start(), Some(id));
+    drop(guard);
+    drop(snapshot);
+}
+
+#[test]
+fn sorted_list() {
+    use crate::sorted_list::OrdFn;
+    use crate::sorted_list::SortedList;
+
+    struct Id;
+    impl OrdFn<u64> for Id {
+        type O = u64;
+        fn key(t: &u64) -> u64 {
+            *t
+        }
+    }
+
+    let s: Superblock = Superblock::anonymous(64).unwrap();
+    let freelist = s.header().freelist as usize;
+    let n = 2000;
+    {
+        let mut root = s.write(1);
+        let mut list = unsafe { SortedList::<u64, Id>::new(&s, &mut root, freelist) };
+        for v in 0..n {
+            list.insert(v).unwrap();
+        }
+    }
+
+    let snapshot = s.snapshot(1).unwrap();
+    {
+        let mut root = s.write(1);
+        let mut list = unsafe { SortedList::<u64, Id>::new(&s, &mut root, freelist) };
+        for v in (0..n).step_by(2) {
+            assert_eq!(list.remove(&v).unwrap(), Some(v));
+        }
+        for v in n..n + 500 {
+            list.insert(v).unwrap();
+        }
+        assert!(list.iter().copied().eq((1..n).step_by(2).chain(n..n + 500)));
+    }
+
+    let values: Vec<u64> = unsafe { snapshot.cursor::<u64>() }
+        .unwrap()
+        .elements()
+        .copied()
+        .collect();
+    assert_eq!(values, (0..n).collect::<Vec<_>>());
+}
@@ -1,3 +1,13 @@
+use crate::base_chunk::ChunkId;
+use crate::base_chunk::ChunkPod;
+use crate::superblock::Error;
+use crate::superblock::LockedList;
+use crate::superblock::Superblock;
+use core::cmp::Ordering;
+use core::marker::PhantomData;
+use core::ops::Bound;
+use core::ops::RangeBounds;
+use core::ops::RangeFull;
 type Chunk<T> = crate::base_chunk::Chunk<T, usize>;
 
 pub trait OrdFn<T> {
@@ -19,21 +29,422 @@ where
     F: OrdFn<T>,
 {
     pub fn insert(&mut self, v: T) -> Result<&mut T, T> {
-        match self.chunk.binary_search_by_key(&F::key(&v), F::key) {
-            Ok(pos) | Err(pos) => self.chunk.insert(pos, v),
+        let pos = self.position(&F::key(&v));
+        self.chunk.insert(pos, v)
+    }
+
+    /// where an element with key belongs.
+    fn position(&self, key: &F::O) -> usize {
+        match self.chunk.binary_search_by_key(key, F::key) {
+            Ok(pos) | Err(pos) => pos,
         }
     }
     pub fn get_mut(&mut self, i: usize) -> Option<&mut T> {
         self.chunk.get_mut(i)
     }
+
+    /// position of the first element that is not before bound.
+    /// this is where a range starting at bound starts inside this chunk.
+    pub fn lower_bound(&self, bound: Bound<&F::O>) -> usize {
+        self.chunk
+            .binary_search_by(|e| {
+                if before(&F::key(e), bound) {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                }
+            })
+            .unwrap_err()
+    }
+}
+
+/// is key in front of a range starting at bound?
+fn before<O: Ord>(key: &O, bound: Bound<&O>) -> bool {
+    match bound {
+        Bound::Included(b) => key < b,
+        Bound::Excluded(b) => key <= b,
+        Bound::Unbounded => false,
+    }
+}
+
+/// is key behind a range ending at bound?
+fn after<O: Ord>(key: &O, bound: Bound<&O>) -> bool {
+    match bound {
+        Bound::Included(b) => key > b,
+        Bound::Excluded(b) => key >= b,
+        Bound::Unbounded => false,
+    }
 }
 
-pub struct SortedList<T, F>
+/// A list of elements, sorted by F, stored in chunks of a Superblock.
+///
+/// Elements with the same key are allowed, they end up next to each other
+/// in an unspecified order.
+///
+/// All operations first find the right chunk through a linear scan of the chunks,
+/// and then do a binary search within it.
+///
+/// The chunks are taken from and returned to a FreeList, which can't be the list itself.
+pub struct SortedList<'s, T, F>
 where
     F: OrdFn<T>,
 {
-    start: usize,
-    mark: std::marker::PhantomData<(T, F)>,
+    list: LockedList<'s, T>,
+    mark: std::marker::PhantomData<F>,
 }
 
-//todo: custom iterator that keeps track of chunk and in-chunk position
+impl<'s, T, F> SortedList<'s, T, F>
+where
+    T: ChunkPod,
+    F: OrdFn<T>,
+{
+    /// unsafe because you need to pass in valid start and freelist entries
+    /// start is from locking the passed list on the correct index,
+    /// and all chunks reachable from it need to be sorted chunks of T.
+    /// freelist is the index of the freelist.
+    ///
+    /// panics if freelist is the entry start is from.
+    pub unsafe fn new(
+        superblock: &'s Superblock,
+        start: &'s mut (usize, usize),
+        freelist: usize,
+    ) -> Self {
+        Self {
+            list: LockedList::new(superblock, start, freelist),
+            mark: PhantomData,
+        }
+    }
+
+    /// safety: id needs to be part of this list
+    /// and you can not hold on to any other reference to that chunk.
+    unsafe fn chunk<'b>(&mut self, id: ChunkId<T>) -> &'b mut SortedChunk<T, F> {
+        // SortedChunk is transparent.
+        (self.list.chunk_mut(id) as *mut Chunk<T> as *mut SortedChunk<T, F>)
+            .as_mut()
+            .unwrap()
+    }
+
+    /// safety: id needs to be part of this list
+    /// and you can not hold on to any mutable reference to that chunk.
+    unsafe fn chunk_ref<'b>(&'b self, id: ChunkId<T>) -> &'b SortedChunk<T, F> {
+        (self.list.get(id) as *const Chunk<T> as *const SortedChunk<T, F>)
+            .as_ref()
+            .unwrap()
+    }
+
+    /// finds the first chunk that does not end before bound.
+    /// if there is no such chunk returns the last chunk.
+    ///
+    /// returns (pre, id) or None if the list is empty.
+    fn find(&self, bound: Bound<&F::O>) -> Option<(Option<ChunkId<T>>, ChunkId<T>)> {
+        let mut pre = None;
+        let mut chunks = self.list.chunks().peekable();
+        while let Some((id, chunk)) = chunks.next() {
+            let reached = match chunk.last() {
+                Some(last) => !before(&F::key(last), bound),
+                None => false,
+            };
+            if reached || chunks.peek().is_none() {
+                return Some((pre, id));
+            }
+            pre = Some(id);
+        }
+        None
+    }
+
+    /// inserts v into the list.
+    /// if the chunk it belongs into is full, that chunk is split in half.
+    ///
+    /// returns Error::Exhausted if a chunk is needed and the freelist has none,
+    /// v is not inserted then.
+    pub fn insert(&mut self, v: T) -> Result<(), Error> {
+        let key = F::key(&v);
+        let id = match self.find(Bound::Included(&key)) {
+            Some((_pre, id)) => self.list.make_mut(id)?.1,
+            None => {
+                let new = self.list.alloc_chunk()?;
+                // new has just been allocated
+                unsafe { self.list.push_front(new) };
+                self.list.sync_start();
+                new
+            }
+        };
+
+        let chunk = unsafe { self.chunk(id) };
+        let pos = chunk.position(&key);
+        if let Err(v) = chunk.chunk.insert(pos, v) {
+            let new = self.list.alloc_chunk()?;
+            // id is part of the list, new has just been allocated
+            unsafe { self.list.insert_split(id, pos, v, new) };
+        }
+        Ok(())
+    }
+
+    /// returns an element with the passed key, if there is one.
+    pub fn get(&self, key: &F::O) -> Option<&T> {
+        let (_pre, id) = self.find(Bound::Included(key))?;
+        let chunk = unsafe { self.chunk_ref(id) };
+        let pos = chunk.lower_bound(Bound::Included(key));
+        chunk.chunk.get(pos).filter(|e| F::key(e) == *key)
+    }
+
+    /// removes an element with the passed key, if there is one.
+    ///
+    /// if the chunk it was in becomes less than half full it is merged
+    /// with one of its neighbours, if they fit together.
+    ///
+    /// returns Error::Exhausted if the chunk is seen by a snapshot
+    /// and the freelist has no chunk to copy it into, nothing is removed then.
+    /// errors from returning an emptied chunk to the freelist are returned after the
+    /// element has been removed.
+    pub fn remove(&mut self, key: &F::O) -> Result<Option<T>, Error> {
+        let (_pre, id) = match self.find(Bound::Included(key)) {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        let chunk = unsafe { self.chunk_ref(id) };
+        let pos = chunk.lower_bound(Bound::Included(key));
+        match chunk.chunk.get(pos) {
+            Some(e) if F::key(e) == *key => {}
+            _ => return Ok(None),
+        }
+        let (pre, id) = self.list.make_mut(id)?;
+        let v = unsafe { self.chunk(id) }.chunk.remove(pos);
+        self.rebalance(pre, id)?;
+        Ok(v)
+    }
+
+    /// merges chunk id with its next or previous chunk if it is underfull
+    /// and they fit into a single chunk.
+    /// id and pre need to be safe to change, see make_mut.
+    ///
+    /// the next chunk is only merged if there is a chunk to copy it into,
+    /// an underfull chunk is fine, so running out of chunks is not an error here.
+    fn rebalance(&mut self, pre: Option<ChunkId<T>>, id: ChunkId<T>) -> Result<(), Error> {
+        let chunk = unsafe { self.chunk(id) };
+        if chunk.chunk.len() >= chunk.chunk.capacity() / 2 {
+            return Ok(());
+        }
+
+        if let Some(next_id) = chunk.chunk.next() {
+            match self.list.make_mut(next_id) {
+                Ok((_id, next_id)) => {
+                    let next = unsafe { self.chunk(next_id) };
+                    if chunk.chunk.append(&mut next.chunk) {
+                        return self.list.unlink(Some(id), next_id);
+                    }
+                }
+                Err(Error::Exhausted) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if let Some(pre) = pre {
+            let pre_chunk = unsafe { self.chunk(pre) };
+            if pre_chunk.chunk.append(&mut chunk.chunk) {
+                return self.list.unlink(Some(pre), id);
+            }
+        }
+        if chunk.chunk.len() == 0 {
+            self.list.unlink(pre, id)?;
+        }
+        Ok(())
+    }
+
+    /// iterates over all elements whose key is inside range, in order.
+    pub fn range<R>(&self, range: R) -> Range<'_, 's, T, F, R>
+    where
+        R: RangeBounds<F::O>,
+    {
+        let (id, pos) = match self.find(range.start_bound()) {
+            Some((_pre, id)) => {
+                let chunk = unsafe { self.chunk_ref(id) };
+                (Some(id), chunk.lower_bound(range.start_bound()))
+            }
+            None => (None, 0),
+        };
+        Range {
+            list: self,
+            id,
+            pos,
+            range,
+        }
+    }
+
+    /// iterates over all elements in order.
+    pub fn iter(&self) -> Range<'_, 's, T, F, RangeFull> {
+        self.range(..)
+    }
+}
+
+/// Iterator over a range of a SortedList.
+/// keeps track of the chunk and the position inside the chunk.
+pub struct Range<'b, 's, T, F, R>
+where
+    F: OrdFn<T>,
+{
+    list: &'b SortedList<'s, T, F>,
+    id: Option<ChunkId<T>>,
+    pos: usize,
+    range: R,
+}
+
+impl<'b, 's, T, F, R> Iterator for Range<'b, 's, T, F, R>
+where
+    T: ChunkPod,
+    F: OrdFn<T>,
+    R: RangeBounds<F::O>,
+{
+    type Item = &'b T;
+    fn next(&mut self) -> Option<&'b T> {
+        while let Some(id) = self.id {
+            let chunk = unsafe { self.list.chunk_ref(id) };
+            if let Some(e) = chunk.chunk.get(self.pos) {
+                if after(&F::key(e), self.range.end_bound()) {
+                    // no need to look at the rest of the list
+                    self.id = None;
+                    return None;
+                }
+                self.pos += 1;
+                return Some(e);
+            }
+            self.id = chunk.chunk.next();
+            self.pos = 0;
+        }
+        None
+    }
+}
+
+/// sorts by the value itself.
+#[cfg(test)]
+struct Id;
+
+#[cfg(test)]
+impl OrdFn<u64> for Id {
+    type O = u64;
+    fn key(t: &u64) -> u64 {
+        *t
+    }
+}
+
+/// the number of elements in every chunk.
+#[cfg(test)]
+fn chunk_lens<F: OrdFn<u64>>(list: &SortedList<u64, F>) -> Vec<usize> {
+    list.list.chunks().map(|(_id, chunk)| chunk.len()).collect()
+}
+
+#[test]
+fn insert_remove() {
+    use rand::seq::SliceRandom;
+
+    fn chunks(list: &SortedList<u64, Id>) -> usize {
+        list.list.chunks().count()
+    }
+
+    let sb = Superblock::anonymous(64).unwrap();
+    let freelist = sb.header().freelist as usize;
+    let mut root = sb.lock(1).unwrap();
+    let mut list = unsafe { SortedList::<u64, Id>::new(&sb, &mut root, freelist) };
+    assert_eq!(list.iter().next(), None);
+    assert_eq!(list.remove(&3).unwrap(), None);
+
+    let n = 5000;
+    let mut values: Vec<u64> = (0..n).collect();
+    values.shuffle(&mut rand::thread_rng());
+    for v in &values {
+        list.insert(*v).unwrap();
+    }
+    let full = chunks(&list);
+    assert!(full > 1);
+
+    assert!(list.iter().copied().eq(0..n));
+    assert!(list.range(100..200).copied().eq(100..200));
+    assert!(list.range(..=10).copied().eq(0..=10));
+    assert!(list.range(n - 10..).copied().eq(n - 10..n));
+    assert!(list.range(n..).next().is_none());
+    assert_eq!(list.get(&1234), Some(&1234));
+    assert_eq!(list.get(&n), None);
+
+    for v in values.iter().filter(|v| *v % 4 != 0) {
+        assert_eq!(list.remove(v).unwrap(), Some(*v));
+        assert_eq!(list.remove(v).unwrap(), None);
+    }
+    assert!(list.iter().copied().eq((0..n).step_by(4)));
+    assert_eq!(list.get(&1234), None);
+    assert!(chunks(&list) < full);
+
+    for v in (0..n).step_by(4) {
+        assert_eq!(list.remove(&v).unwrap(), Some(v));
+    }
+    assert_eq!(chunks(&list), 0);
+    assert_eq!(list.iter().next(), None);
+}
+
+#[test]
+fn merge_into_pre() {
+    let sb = Superblock::anonymous(64).unwrap();
+    let freelist = sb.header().freelist as usize;
+    let mut root = sb.lock(1).unwrap();
+    let mut list = unsafe { SortedList::<u64, Id>::new(&sb, &mut root, freelist) };
+    let mut n = 2000;
+    for v in 0..n {
+        list.insert(v).unwrap();
+    }
+    let cap = list.list.chunks().next().unwrap().1.capacity();
+    let before = chunk_lens(&list);
+    let (pre, last) = (before[before.len() - 2], before[before.len() - 1]);
+    assert!(last >= cap / 2);
+
+    // the last chunk has no next, so it can only be merged into the one in front of it
+    while chunk_lens(&list).len() == before.len() {
+        n -= 1;
+        assert_eq!(list.remove(&n).unwrap(), Some(n));
+    }
+    let after = chunk_lens(&list);
+    assert_eq!(after.len(), before.len() - 1);
+    assert_eq!(n as usize, 2000 - (last - cap / 2 + 1));
+    assert_eq!(*after.last().unwrap(), pre + cap / 2 - 1);
+    assert!(list.iter().copied().eq(0..n));
+}
+
+#[test]
+fn duplicate_keys() {
+    /// 1000 values per key
+    struct Thousands;
+    impl OrdFn<u64> for Thousands {
+        type O = u64;
+        fn key(t: &u64) -> u64 {
+            *t / 1000
+        }
+    }
+
+    let sb = Superblock::anonymous(64).unwrap();
+    let freelist = sb.header().freelist as usize;
+    let mut root = sb.lock(1).unwrap();
+    let mut list = unsafe { SortedList::<u64, Thousands>::new(&sb, &mut root, freelist) };
+    for v in 0..3000 {
+        list.insert(v).unwrap();
+    }
+    let cap = list.list.chunks().next().unwrap().1.capacity();
+    // key 1 does not fit into a single chunk
+    assert!(cap < 1000);
+
+    let ones = |list: &SortedList<u64, Thousands>| {
+        let mut ones: Vec<u64> = list.range(1..=1).copied().collect();
+        ones.sort_unstable();
+        ones
+    };
+    assert_eq!(ones(&list), (1000..2000).collect::<Vec<_>>());
+    assert_eq!(list.get(&1).map(|v| *v / 1000), Some(1));
+
+    for _ in 0..1000 {
+        assert_eq!(list.remove(&1).unwrap().map(|v| v / 1000), Some(1));
+    }
+    assert_eq!(list.remove(&1).unwrap(), None);
+    assert_eq!(list.get(&1), None);
+    assert!(ones(&list).is_empty());
+    // equal keys are in no particular order
+    let mut rest: Vec<u64> = list.iter().copied().collect();
+    assert!(rest.windows(2).all(|w| w[0] / 1000 <= w[1] / 1000));
+    rest.sort_unstable();
+    assert_eq!(rest, (0..1000).chain(2000..3000).collect::<Vec<_>>());
+}
@@ -1,87 +1,1575 @@
+use crate::base_chunk::ChunkId;
+use crate::base_chunk::ChunkLen;
+use crate::base_chunk::ChunkPod;
+use crate::base_chunk::ChunkSize;
+use crate::base_chunk::Size;
+use crate::base_chunk::DEFAULT_CHUNK_SIZE;
+use crate::freelist::FreeList;
+use crate::freelist::Policy;
+use crate::slicelist::Cursor;
+use crate::slicelist::CursorMut;
+use crate::slicelist::SliceList;
+use crate::snapshot::Frozen;
+use crate::snapshot::Snapshot;
 use core::mem::MaybeUninit;
-use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::AtomicUsize;
 use core::sync::atomic::Ordering;
-type Chunk<T> = crate::base_chunk::Chunk<T, usize>;
+use memmap::MmapMut;
+use memmap::MmapOptions;
+use std::collections::HashMap;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::ops::Deref;
+use std::ops::DerefMut;
+use std::path::Path;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::sync::PoisonError;
+use std::time::Duration;
+use std::time::Instant;
+type Chunk<T, const SIZE: usize = DEFAULT_CHUNK_SIZE> = crate::base_chunk::Chunk<T, usize, SIZE>;
 
-pub struct Superblock {
-    c: *mut [Chunk<u8>],
+/// size of a single chunk in the backing file, unless the superblock uses a different SIZE.
+/// every chunk starts at a multiple of this.
+pub const CHUNK_SIZE: usize = core::mem::size_of::<Chunk<u8>>();
+
+/// identifies a file as a superblock, first thing in chunk 0.
+pub const MAGIC: [u8; 8] = *b"blocklyr";
+/// bumped on every incompatible change to the on-disk layout.
+pub const VERSION: u32 = 4;
+
+/// chunk containing the Header
+pub const HEADER_CHUNK: usize = 0;
+/// chunk containing the lock table
+pub const LOCK_TABLE_CHUNK: usize = 1;
+/// chunk the freelist starts out in.
+/// the freelist may move its first chunk later on, its lock table entry is authoritative.
+pub const FREELIST_CHUNK: usize = 2;
+/// lock table entry holding the (start, _) of the freelist
+pub const FREELIST_ENTRY: usize = 0;
+
+/// lock word of an entry nobody is holding.
+const UNLOCKED: usize = 0;
+/// lock word of a write-locked entry.
+/// anything between UNLOCKED and WRITE_LOCKED is the number of readers.
+const WRITE_LOCKED: usize = usize::MAX;
+
+type LockEntry = (AtomicUsize, (usize, usize));
+
+/// Describes the layout of a Superblock.
+/// Stored as the only element of chunk 0.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub magic: [u8; 8],
+    pub version: u32,
+    /// size of a chunk in bytes
+    pub chunk_size: u32,
+    /// size of a pointer in bytes, all next_hints are this wide
+    pub ptr_width: u32,
+    /// number of entries in the lock table
+    pub lock_entries: u32,
+    /// chunk containing the lock table
+    pub lock_table: u64,
+    /// lock table entry that holds the first chunk of the freelist
+    pub freelist: u64,
+    /// first chunk of the write-ahead log, see the wal module
+    pub log: u64,
+    /// number of chunks the log can save, they follow right after log
+    pub log_slots: u64,
+    /// first chunk of the checksum table, 0 if there are no checksums.
+    /// see the checksum module
+    pub checksums: u64,
+    /// number of chunks in the checksum table, they end right in front of log
+    pub checksum_chunks: u64,
+}
+
+impl Header {
+    /// the header this version writes for a superblock of n_chunks chunks of SIZE bytes
+    fn current<const SIZE: usize>(n_chunks: usize, checksums: bool) -> Self
+    where
+        Size<SIZE>: ChunkSize,
+    {
+        let log_slots = crate::wal::log_slots(n_chunks);
+        let log = n_chunks - log_slots - 1;
+        let checksum_chunks = if checksums {
+            crate::checksum::table_chunks::<SIZE>(n_chunks)
+        } else {
+            0
+        };
+        Self {
+            magic: MAGIC,
+            version: VERSION,
+            chunk_size: SIZE as u32,
+            ptr_width: crate::base_chunk::PTR_SIZE as u32,
+            lock_entries: lock_capacity::<SIZE>() as u32,
+            lock_table: LOCK_TABLE_CHUNK as u64,
+            freelist: FREELIST_ENTRY as u64,
+            log: log as u64,
+            log_slots: log_slots as u64,
+            checksums: if checksums { log - checksum_chunks } else { 0 } as u64,
+            checksum_chunks: checksum_chunks as u64,
+        }
+    }
+}
+
+/// the smallest superblock that fits the bootstrap layout, a log and one more chunk
+const MIN_CHUNKS: usize = FREELIST_CHUNK + 4;
+
+/// the smallest superblock of n_chunks chunks that also fits a checksum table, if wanted.
+fn min_chunks<const SIZE: usize>(n_chunks: usize, checksums: bool) -> usize
+where
+    Size<SIZE>: ChunkSize,
+{
+    if checksums {
+        MIN_CHUNKS + crate::checksum::table_chunks::<SIZE>(n_chunks)
+    } else {
+        MIN_CHUNKS
+    }
+}
+
+/// number of entries that fit into the lock table chunk
+fn lock_capacity<const SIZE: usize>() -> usize
+where
+    Size<SIZE>: ChunkSize,
+{
+    Chunk::<LockEntry, SIZE>::BUF_SIZE / core::mem::size_of::<LockEntry>()
+}
+
+/// Reasons a superblock can not be created or opened.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// the file does not start with MAGIC, contains what was found instead.
+    /// probably not a superblock at all.
+    Magic([u8; 8]),
+    /// the file was written with a different, incompatible, format version.
+    Version(u32),
+    /// the file was written with a different chunk size, contains that size.
+    ChunkSize(u32),
+    /// the file was written on a platform with a different pointer width.
+    PointerWidth(u32),
+    /// the header or the lock table don't describe a layout this version understands,
+    /// or the file is too small to contain it.
+    Layout,
+    /// the write-ahead log has no space left to save another chunk.
+    /// commit and start a new transaction.
+    LogFull,
+    /// this chunk does not match its checksum,
+    /// it has been changed outside of this library since the last flush.
+    Corrupt(ChunkId<u8>),
+    /// the freelist has no chunk left, to copy a frozen chunk into
+    /// or to store the entries of a list in.
+    Exhausted,
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Magic(m) => write!(f, "not a superblock, found magic {:?}", m),
+            Error::Version(v) => {
+                write!(f, "unsupported format version {}, expected {}", v, VERSION)
+            }
+            Error::ChunkSize(s) => write!(f, "chunk size is {}, opened with another size", s),
+            Error::PointerWidth(w) => write!(
+                f,
+                "pointer width is {}, expected {}",
+                w,
+                crate::base_chunk::PTR_SIZE
+            ),
+            Error::Layout => write!(f, "invalid superblock layout"),
+            Error::LogFull => write!(f, "write-ahead log is full"),
+            Error::Corrupt(id) => write!(f, "chunk {} does not match its checksum", id.pos()),
+            Error::Exhausted => write!(f, "freelist is exhausted"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// A bunch of chunks, backed by a memory-mapped file (or anonymous memory).
+///
+/// The bootstrap layout is:
+/// * chunk 0 contains the Header
+/// * chunk 1 contains the lock table, every entry holds the root of one list
+/// * chunk 2 is the first chunk of the freelist, whose root is lock table entry 0
+/// * the last chunks contain the write-ahead log, see the wal module
+/// * if enabled, the chunks right in front of the log contain checksums, see the checksum module
+///
+/// every other chunk is managed through the freelist.
+/// Chunks reference each other by their offset inside the Superblock.
+///
+/// Every chunk is SIZE bytes big, a superblock can only be opened with the size it was
+/// created with.
+pub struct Superblock<const SIZE: usize = DEFAULT_CHUNK_SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    c: *mut [Chunk<u8, SIZE>],
+    /// owns the memory c is pointing to.
+    /// c is only valid as long as this is alive, which is why this is not exposed.
+    map: MmapMut,
+    /// where c starts inside of map, see map_aligned.
+    offset: usize,
+    /// held by the running transaction, there is only one log.
+    pub(crate) log: Mutex<()>,
+    /// held by threads waiting for a lock table entry while they try it and go to sleep,
+    /// and taken by unlock before it wakes them, so no wakeup is lost in between.
+    waiting: Mutex<()>,
+    /// number of threads waiting for any lock table entry.
+    /// unlock only takes the waiting mutex if this is not 0.
+    waiters: AtomicUsize,
+    /// one per lock table entry, notified when it is unlocked.
+    unlocked: Box<[Condvar]>,
+    /// chunks that are seen by at least one snapshot, see the snapshot module.
+    pub(crate) frozen: Mutex<HashMap<usize, Frozen>>,
+    /// one bit per chunk, set once the chunk has been checked or written to since opening.
+    /// see the checksum module.
+    pub(crate) verified: Box<[AtomicU64]>,
+    /// one bit per chunk, set once the chunk has been written to since the last flush.
+    /// see the checksum module.
+    pub(crate) dirty: Box<[AtomicU64]>,
+    /// held while the checksum table is read or written,
+    /// concurrent flushes would race on its chunks otherwise.
+    pub(crate) checksums: Mutex<()>,
+    /// the chunks of the checksum table.
+    /// empty until the header has been written or validated, so nothing is checksummed before.
+    pub(crate) checksum_table: std::ops::Range<usize>,
 }
 
 // every call on this is either accessing a mutex or marked unsafe
-unsafe impl Sync for Superblock {}
+unsafe impl<const SIZE: usize> Sync for Superblock<SIZE> where Size<SIZE>: ChunkSize {}
+// the mapping does not care which thread it is accessed from
+unsafe impl<const SIZE: usize> Send for Superblock<SIZE> where Size<SIZE>: ChunkSize {}
+
+impl<const SIZE: usize> Superblock<SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    /// creates a new file at path, sized to hold n_chunks chunks, and maps it.
+    /// fails if the file already exists.
+    ///
+    /// writes the header, an empty lock table and the freelist.
+    pub fn create<P: AsRef<Path>>(path: P, n_chunks: usize) -> Result<Self, Error> {
+        Self::create_with(path, n_chunks, false)
+    }
+
+    /// like create, but also keeps a checksum of every chunk, see the checksum module.
+    /// this costs a few chunks at the end of the file.
+    pub fn create_checksummed<P: AsRef<Path>>(path: P, n_chunks: usize) -> Result<Self, Error> {
+        Self::create_with(path, n_chunks, true)
+    }
+
+    fn create_with<P: AsRef<Path>>(
+        path: P,
+        n_chunks: usize,
+        checksums: bool,
+    ) -> Result<Self, Error> {
+        if n_chunks < min_chunks::<SIZE>(n_chunks, checksums) {
+            return Err(Error::Layout);
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)?;
+        file.set_len((n_chunks * SIZE) as u64)?;
+        let mut s = Self::from_map(Some(&file), n_chunks * SIZE)?;
+        s.init(checksums);
+        s.flush()?;
+        Ok(s)
+    }
+
+    /// maps a file previously created through create().
+    ///
+    /// validates the header before handing out the superblock.
+    /// if a transaction was interrupted it is rolled back,
+    /// the rest of the contents are taken as they are.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let len = file.metadata()?.len() as usize;
+        let mut s = Self::from_map(Some(&file), len)?;
+        s.validate()?;
+        crate::wal::recover(&s)?;
+        Ok(s)
+    }
+
+    /// creates a superblock that is not backed by any file.
+    /// everything is lost on drop, mostly useful for testing.
+    pub fn anonymous(n_chunks: usize) -> Result<Self, Error> {
+        if n_chunks < MIN_CHUNKS {
+            return Err(Error::Layout);
+        }
+        let mut s = Self::from_map(None, n_chunks * SIZE)?;
+        s.init(false);
+        Ok(s)
+    }
+
+    /// maps len bytes of file, or anonymous memory if there is none.
+    fn from_map(file: Option<&File>, len: usize) -> Result<Self, Error> {
+        if len == 0 || len % SIZE != 0 {
+            return Err(Error::Layout);
+        }
+        let (mut map, offset) = Self::map_aligned(file, len)?;
+        let base = unsafe { map.as_mut_ptr().add(offset) } as *mut Chunk<u8, SIZE>;
+        assert_eq!(base as usize % core::mem::align_of::<Chunk<u8, SIZE>>(), 0);
+        let n_chunks = len / SIZE;
+        let c = core::ptr::slice_from_raw_parts_mut(base, n_chunks);
+        let bitmap = || {
+            (0..(n_chunks + 63) / 64)
+                .map(|_| AtomicU64::new(0))
+                .collect()
+        };
+        Ok(Self {
+            c,
+            map,
+            offset,
+            log: Mutex::new(()),
+            waiting: Mutex::new(()),
+            waiters: AtomicUsize::new(0),
+            // the lock table never has more entries than fit into its chunk
+            unlocked: (0..SIZE / core::mem::size_of::<LockEntry>())
+                .map(|_| Condvar::new())
+                .collect(),
+            frozen: Mutex::new(HashMap::new()),
+            verified: bitmap(),
+            dirty: bitmap(),
+            checksums: Mutex::new(()),
+            checksum_table: 0..0,
+        })
+    }
+
+    /// maps len bytes of file so that they start at a multiple of SIZE.
+    /// returns the map and the offset of the first byte inside of it.
+    ///
+    /// mappings are only page-aligned. if that is not enough for our chunks,
+    /// SIZE more bytes of address space are reserved and the file is mapped over an aligned
+    /// part of that. the map owns the whole reservation, so the file is unmapped with it.
+    fn map_aligned(file: Option<&File>, len: usize) -> io::Result<(MmapMut, usize)> {
+        let map = match file {
+            Some(file) => unsafe { MmapOptions::new().len(len).map_mut(file)? },
+            None => MmapMut::map_anon(len)?,
+        };
+        if map.as_ptr() as usize % SIZE == 0 {
+            return Ok((map, 0));
+        }
+        drop(map);
+
+        let mut reserved = MmapMut::map_anon(len + SIZE)?;
+        let offset = reserved.as_ptr().align_offset(SIZE);
+        if let Some(file) = file {
+            Self::map_fixed(file, unsafe { reserved.as_mut_ptr().add(offset) }, len)?;
+        }
+        Ok((reserved, offset))
+    }
+
+    /// maps len bytes of file at addr, replacing whatever was mapped there.
+    #[cfg(unix)]
+    fn map_fixed(file: &File, addr: *mut u8, len: usize) -> io::Result<()> {
+        use std::os::unix::io::AsRawFd;
+        let ptr = unsafe {
+            libc::mmap(
+                addr as *mut libc::c_void,
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_FIXED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// there is no way to place a mapping here, so files can only hold chunks up to page size.
+    #[cfg(not(unix))]
+    fn map_fixed(_file: &File, _addr: *mut u8, _len: usize) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "chunks bigger than a page are only supported on unix",
+        ))
+    }
+
+    /// writes the bootstrap layout
+    fn init(&mut self, checksums: bool) {
+        let header = Header::current::<SIZE>(self.len(), checksums);
+
+        // we have exclusive access, nobody can be holding any locks
+        let chunk =
+            Chunk::initialize(unsafe { self.get_mut::<Header>(ChunkId::new(HEADER_CHUNK)) });
+        chunk.push(header).unwrap_none();
+
+        let locks =
+            Chunk::initialize(unsafe { self.get_mut::<LockEntry>(ChunkId::new(LOCK_TABLE_CHUNK)) });
+        for _ in 0..header.lock_entries {
+            use crate::base_chunk::Link;
+            let empty = Link::<Chunk<u8, SIZE>>::empty();
+            locks
+                .push((AtomicUsize::new(UNLOCKED), (empty, 0)))
+                .unwrap_none();
+        }
+        locks[FREELIST_ENTRY].1 .0 = FREELIST_CHUNK;
+
+        // we have exclusive access
+        let chunks = unsafe { core::slice::from_raw_parts_mut(self.base(), self.len()) };
+        let mut freelist = FreeList::<u8, SIZE>::new(chunks, ChunkId::new(FREELIST_CHUNK));
+        // the freelist considers everything in front of its initial chunk as free,
+        // those are the header and lock table.
+        assert!(freelist.mark_used(0, FREELIST_CHUNK as u32));
+        let log_chunks = header.log_slots as u32 + 1;
+        assert!(freelist.mark_used(header.log as u32, log_chunks));
+        if checksums {
+            let (table, count) = (header.checksums as usize, header.checksum_chunks as usize);
+            assert!(freelist.mark_used(table as u32, count as u32));
+            self.checksum_table = table..table + count;
+            crate::checksum::init(self);
+        }
+        crate::wal::init(self);
+    }
+
+    /// checks that the header matches what this version writes
+    /// and that the lock table is where the header says.
+    fn validate(&mut self) -> Result<(), Error> {
+        // do not trust the length stored in the chunk, only look at the first element.
+        // any bit pattern is a valid Header.
+        let chunk = unsafe { self.get::<Header>(ChunkId::new(HEADER_CHUNK)).get_ref() };
+        let header = unsafe { *chunk.as_uninit_slice()[0].as_ptr() };
+        let checksums = header.checksums != 0;
+        if self.len() < min_chunks::<SIZE>(self.len(), checksums) {
+            return Err(Error::Layout);
+        }
+        let expected = Header::current::<SIZE>(self.len(), checksums);
+
+        if header.magic != expected.magic {
+            return Err(Error::Magic(header.magic));
+        }
+        if header.version != expected.version {
+            return Err(Error::Version(header.version));
+        }
+        if header.chunk_size != expected.chunk_size {
+            return Err(Error::ChunkSize(header.chunk_size));
+        }
+        if header.ptr_width != expected.ptr_width {
+            return Err(Error::PointerWidth(header.ptr_width));
+        }
+        if header != expected || chunk.len() != 1 {
+            return Err(Error::Layout);
+        }
+
+        let len = unsafe { *Chunk::len_ptr(self.lock_table()) };
+        if len.to_usize() != header.lock_entries as usize {
+            return Err(Error::Layout);
+        }
+        let table = header.checksums as usize;
+        self.checksum_table = table..table + header.checksum_chunks as usize;
+        Ok(())
+    }
 
-impl Superblock {
-    pub fn lock(&self, pos: usize) -> Option<&mut (usize, usize)> {
-        let superblock = self.c as *mut Chunk<u8> as *mut Chunk<(AtomicBool, (usize, usize))>;
+    /// the header, as validated during open
+    pub fn header(&self) -> &Header {
+        // the header is only ever written on creation
+        let chunk = unsafe { self.get::<Header>(ChunkId::new(HEADER_CHUNK)).get_ref() };
+        &chunk[0]
+    }
+
+    pub(crate) fn lock_table(&self) -> *mut Chunk<LockEntry, SIZE> {
+        let pos = self.header().lock_table as usize;
+        // in bounds, validated on open
+        unsafe { (self.c as *mut Chunk<u8, SIZE>).add(pos) as *mut Chunk<LockEntry, SIZE> }
+    }
+
+    /// the first of the len() chunks, including the bootstrap ones.
+    /// lists on top of this only reference the chunks they reach, never all of them at once,
+    /// so several guards can walk their lists at the same time.
+    /// accessing a chunk through this has the same requirements as get or get_mut.
+    pub(crate) fn base(&self) -> *mut MaybeUninit<Chunk<u8, SIZE>> {
+        self.c as *mut MaybeUninit<Chunk<u8, SIZE>>
+    }
+
+    /// number of chunks in this superblock, including the bootstrap ones.
+    pub fn len(&self) -> usize {
+        // anything left over after the chunks is less than a chunk, see map_aligned
+        (self.map.len() - self.offset) / SIZE
+    }
+
+    /// writes all changes to the backing file and waits for that to finish.
+    /// does nothing for anonymous superblocks.
+    ///
+    /// if there are checksums, the ones of all chunks written since the last flush are
+    /// recomputed first. whatever is in those chunks at that point is taken as correct.
+    pub fn flush(&self) -> io::Result<()> {
+        crate::checksum::update(self, 0, self.len());
+        self.map.flush()
+    }
+
+    /// starts writing all changes to the backing file, but does not wait for it.
+    /// checksums are recomputed like in flush.
+    pub fn flush_async(&self) -> io::Result<()> {
+        crate::checksum::update(self, 0, self.len());
+        self.map.flush_async()
+    }
+
+    /// writes count chunks starting at id to the backing file and waits for that to finish.
+    /// if there are checksums, the ones of those chunks that have been written since the last
+    /// flush are recomputed and written as well.
+    pub fn flush_chunks<T>(&self, id: ChunkId<T>, count: usize) -> io::Result<()> {
+        let pos = id.pos();
+        let table = crate::checksum::update(self, pos, count);
+        self.map
+            .flush_range(self.offset + pos * SIZE, count * SIZE)?;
+        if !table.is_empty() {
+            self.map
+                .flush_range(self.offset + table.start * SIZE, table.len() * SIZE)?;
+        }
+        Ok(())
+    }
+
+    /// checks chunk id against its checksum, see the checksum module.
+    /// always succeeds if the superblock has no checksums.
+    ///
+    /// chunks that have been changed since the last flush don't match,
+    /// so only use this on chunks that are not being written to.
+    ///
+    /// panics if id is out of bounds.
+    pub fn verify<T>(&self, id: ChunkId<T>) -> Result<(), Error> {
+        crate::checksum::verify(self, id.pos())
+    }
+
+    /// checks that every list is intact and that the freelist agrees with them,
+    /// returns everything that is wrong. see the check module.
+    ///
+    /// needs exclusive access, so nothing can change while checking.
+    pub fn check(&mut self) -> crate::check::Report {
+        crate::check::check(self)
+    }
+
+    /// starts a transaction, waiting for the running one to finish if there is one.
+    /// see the wal module.
+    pub fn transaction(&self) -> crate::wal::Transaction<'_, SIZE> {
+        crate::wal::Transaction::new(self)
+    }
+
+    /// takes a snapshot of the list rooted in lock table entry pos.
+    /// waits while someone is holding the write lock of the entry.
+    /// see the snapshot module.
+    ///
+    /// every chunk of the list is loaded, returns Error::Corrupt for the first one that does not
+    /// match its checksum.
+    pub fn snapshot(&self, pos: usize) -> Result<Snapshot<'_, SIZE>, Error> {
+        Snapshot::new(self, pos)
+    }
+
+    /// copies the raw contents of chunk from over chunk to.
+    ///
+    /// safety: both need to be in bounds, nobody can be writing from
+    /// and nobody else can be accessing to.
+    pub(crate) unsafe fn copy_chunk(&self, from: usize, to: usize) {
+        // we only copy bytes, so whatever is in there does not matter.
+        let base = self.c as *mut Chunk<u8, SIZE> as *mut u8;
+        let from = base.add(from * SIZE);
+        crate::checksum::mark_written(self, to);
+        let to = base.add(to * SIZE);
+        std::ptr::copy_nonoverlapping(from, to, SIZE);
+    }
+
+    /// copies the length and the roots of the lock table saved in chunk from
+    /// over the live lock table, keeping the live lock words.
+    /// the saved lock words are whatever they were when the copy was taken,
+    /// the live ones belong to whoever is holding them now.
+    ///
+    /// safety: from needs to be in bounds and nobody can be writing it,
+    /// nobody can be accessing the roots.
+    pub(crate) unsafe fn restore_lock_table(&self, from: usize) {
+        let base = self.c as *mut Chunk<u8, SIZE> as *mut u8;
+        let from = base.add(from * SIZE) as *mut Chunk<LockEntry, SIZE>;
+        let to = self.lock_table();
+        crate::checksum::mark_written(self, self.header().lock_table as usize);
+        let len = *Chunk::len_ptr(from);
+        for i in 0..len.to_usize() {
+            let saved = (from as *mut LockEntry).add(i);
+            let live = (to as *mut LockEntry).add(i);
+            (*live).1 = (*saved).1;
+        }
+        *Chunk::len_ptr(to) = len;
+    }
+
+    /// unlocks every lock table entry, no matter who is holding it.
+    ///
+    /// safety: nobody can be holding any guard.
+    pub(crate) unsafe fn reset_locks(&self) {
+        let table = self.lock_table().as_mut().unwrap();
+        for entry in table.iter_mut() {
+            entry.0.store(UNLOCKED, Ordering::SeqCst);
+        }
+    }
+
+    /// the lock word and the root of lock table entry pos.
+    ///
+    /// panics if pos is not inside the lock table.
+    fn lock_entry(&self, pos: usize) -> (&AtomicUsize, *mut (usize, usize)) {
+        let superblock = self.lock_table();
         let len = unsafe { *Chunk::len_ptr(superblock) };
-        if pos > len as usize {
+        if pos >= len.to_usize() {
             panic!("called lock on an out of bounds element, this should never happen. only call lock on known elements")
         }
-        let entries = superblock as *mut (AtomicBool, (usize, usize));
-        let entry = unsafe { entries.add(pos) };
-        let entry = entry as *mut AtomicBool;
-        let entry = unsafe { entry.as_ref().unwrap() };
+        let entry = unsafe { (superblock as *mut LockEntry).add(pos) };
+        // stay in pointer space for the root until we know what kind of access we got
+        unsafe { (&(*entry).0, &mut (*entry).1 as *mut _) }
+    }
 
+    /// panics if root is the root stored in lock table entry `freelist`.
+    /// a list can't take chunks from itself, locking the freelist would wait forever
+    /// for the guard of the list.
+    pub(crate) fn assert_other_freelist(&self, freelist: usize, root: &(usize, usize)) {
+        let (_word, freelist_root) = self.lock_entry(freelist);
+        assert!(
+            !core::ptr::eq(root, freelist_root),
+            "a list can't use itself as its freelist"
+        );
+    }
+
+    /// write-locks lock table entry pos, if nobody else is holding it.
+    /// the entry is unlocked once the returned guard is dropped.
+    ///
+    /// panics if pos is not inside the lock table.
+    pub fn lock(&self, pos: usize) -> Option<SuperblockGuard<'_, SIZE>> {
+        let (word, root) = self.lock_entry(pos);
         // todo: maybe AcqRel is enough here
-        if let false = entry.compare_and_swap(false, true, Ordering::SeqCst) {
-            let superblock = superblock as *mut (AtomicBool, (usize, usize));
-            // this is probably ok as we would have paniced on oob when getting the lock.
-            let entry = unsafe { superblock.add(pos) };
-            let entry = unsafe { entry.as_mut() }.unwrap();
-            Some(&mut entry.1)
-        } else {
-            None
-        }
+        word.compare_exchange(UNLOCKED, WRITE_LOCKED, Ordering::SeqCst, Ordering::SeqCst)
+            .ok()?;
+        // we are the only one holding the entry
+        let root = unsafe { root.as_mut() }.unwrap();
+        Some(SuperblockGuard {
+            superblock: self,
+            pos,
+            root,
+        })
     }
 
-    /// safety: only ever call this with a pos that you have previously locked through the
-    /// lock() call.
+    /// write-locks lock table entry pos, waiting for as long as it takes.
     ///
-    /// this is marked as unsafe so you can't safely do
-    /// unlock(foregin); lock(foregin); access(foregin);
-    pub unsafe fn unlock(&self, pos: usize) -> () {
-        let superblock = self.c as *mut Chunk<u8> as *mut Chunk<(AtomicBool, (usize, usize))>;
-        // im not 100% sure this is safe, as we are giving out &mut references
-        // maybe i need to stay in pointer space until i get the atomic locked
-        let lockblock = superblock.as_ref().unwrap();
-        let entry = &lockblock[pos];
+    /// sleeps until the entry is unlocked, then tries again.
+    /// new readers can get in while this is waiting, so this might wait for a long time
+    /// if the entry is read-locked a lot.
+    pub fn write(&self, pos: usize) -> SuperblockGuard<'_, SIZE> {
+        self.wait_until(pos, None, || self.lock(pos)).unwrap()
+    }
+
+    /// write-locks lock table entry pos, waiting for at most timeout.
+    /// returns None if the entry is still locked by then.
+    pub fn try_lock_for(&self, pos: usize, timeout: Duration) -> Option<SuperblockGuard<'_, SIZE>> {
+        self.wait_until(pos, Some(Instant::now() + timeout), || self.lock(pos))
+    }
+
+    /// read-locks lock table entry pos, unless it is write-locked.
+    /// any number of readers can hold an entry at the same time.
+    /// the entry is unlocked once the returned guard is dropped.
+    ///
+    /// panics if pos is not inside the lock table.
+    pub fn try_read(&self, pos: usize) -> Option<SuperblockReadGuard<'_, SIZE>> {
+        let (word, root) = self.lock_entry(pos);
+        let mut current = word.load(Ordering::SeqCst);
+        loop {
+            // also fails if there are so many readers that one more would look like a writer
+            if current >= WRITE_LOCKED - 1 {
+                return None;
+            }
+            // todo: maybe AcqRel is enough here
+            match word.compare_exchange_weak(
+                current,
+                current + 1,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            ) {
+                Ok(_) => break,
+                Err(actual) => current = actual,
+            }
+        }
+        // nobody is writing the entry while we hold it
+        let root = unsafe { root.as_ref() }.unwrap();
+        Some(SuperblockReadGuard {
+            superblock: self,
+            pos,
+            root,
+        })
+    }
+
+    /// read-locks lock table entry pos, waiting for as long as it takes.
+    /// see write for how waiting works.
+    pub fn read(&self, pos: usize) -> SuperblockReadGuard<'_, SIZE> {
+        self.wait_until(pos, None, || self.try_read(pos)).unwrap()
+    }
+
+    /// read-locks lock table entry pos, waiting for at most timeout.
+    /// returns None if the entry is still write-locked by then.
+    pub fn try_read_for(
+        &self,
+        pos: usize,
+        timeout: Duration,
+    ) -> Option<SuperblockReadGuard<'_, SIZE>> {
+        self.wait_until(pos, Some(Instant::now() + timeout), || self.try_read(pos))
+    }
+
+    /// calls acquire until it succeeds or deadline has passed, forever if there is none.
+    /// sleeps on entry pos in between, acquire has to try locking it.
+    fn wait_until<G>(
+        &self,
+        pos: usize,
+        deadline: Option<Instant>,
+        mut acquire: impl FnMut() -> Option<G>,
+    ) -> Option<G> {
+        if let Some(guard) = acquire() {
+            return Some(guard);
+        }
+        let mut waiting = self.waiting.lock().unwrap_or_else(PoisonError::into_inner);
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        let guard = loop {
+            // an unlock after this sees waiters and can't notify before we are asleep
+            if let Some(guard) = acquire() {
+                break Some(guard);
+            }
+            let unlocked = &self.unlocked[pos];
+            waiting = match deadline {
+                None => unlocked
+                    .wait(waiting)
+                    .unwrap_or_else(PoisonError::into_inner),
+                Some(d) => {
+                    let now = Instant::now();
+                    if now >= d {
+                        break None;
+                    }
+                    unlocked
+                        .wait_timeout(waiting, d - now)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+            };
+        };
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+        guard
+    }
 
+    /// wakes the threads waiting for entry pos, if there are any.
+    fn wake(&self, pos: usize) {
         // todo: maybe AcqRel is enough here
-        if let true = entry.0.compare_and_swap(false, true, Ordering::SeqCst) {
-            ()
-        } else {
+        if self.waiters.load(Ordering::SeqCst) != 0 {
+            // waiters hold this from their last try until they are asleep
+            drop(self.waiting.lock().unwrap_or_else(PoisonError::into_inner));
+            self.unlocked[pos].notify_all();
+        }
+    }
+
+    /// safety: only ever call this with a pos that you have previously write-locked through the
+    /// lock() call, and only once per lock() call.
+    /// SuperblockGuard takes care of this.
+    unsafe fn unlock(&self, pos: usize) {
+        let (word, _root) = self.lock_entry(pos);
+        // todo: maybe AcqRel is enough here
+        if word
+            .compare_exchange(WRITE_LOCKED, UNLOCKED, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
             panic!("tried to unlock an unlocked mutex");
         }
+        self.wake(pos);
+    }
+
+    /// safety: only ever call this with a pos that you have previously read-locked through the
+    /// try_read() call, and only once per try_read() call.
+    /// SuperblockReadGuard takes care of this.
+    unsafe fn unlock_read(&self, pos: usize) {
+        let (word, _root) = self.lock_entry(pos);
+        // todo: maybe AcqRel is enough here
+        let prev = word.fetch_sub(1, Ordering::SeqCst);
+        if prev == UNLOCKED || prev == WRITE_LOCKED {
+            panic!("tried to read-unlock an entry that was not read-locked");
+        }
+        self.wake(pos);
+    }
+
+    /// runs f on the FreeList whose root is stored in lock table entry `freelist`.
+    /// waits until that entry can be locked, writes back the root afterwards.
+    ///
+    /// the root is (initial chunk, FreeList::state), so the policy and where the last
+    /// allocation ended are kept as well.
+    ///
+    /// the chunks of the freelist are loaded first, returns Error::Corrupt without calling f
+    /// if one of them does not match its checksum.
+    pub(crate) fn with_freelist<R>(
+        &self,
+        freelist: usize,
+        f: impl FnOnce(&mut FreeList<u8, SIZE>) -> R,
+    ) -> Result<R, Error> {
+        let mut root = self.write(freelist);
+        // the lock table entry is locked, so we have exclusive access to the freelist chunks.
+        let initial = root.start().expect("freelists always have a chunk");
+        unsafe { self.load_list(Some(initial))? };
+        let state = root.1 as u64;
+        let mut list = unsafe { FreeList::from_raw(self.base(), self.len(), initial, state) };
+        // chunks can leave the list as well as join it, so mark before and after
+        self.mark_freelist_written(&list);
+        let r = f(&mut list);
+        self.mark_freelist_written(&list);
+        root.set_start(Some(list.initial()));
+        root.1 = list.state() as usize;
+        Ok(r)
+    }
+
+    /// loads every chunk of the list starting at start, see load.
+    /// each chunk is checked before its link is followed, so a corrupt link is never followed.
+    ///
+    /// safety: same as load, for every chunk of the list.
+    pub(crate) unsafe fn load_list<T>(&self, start: Option<ChunkId<T>>) -> Result<(), Error> {
+        let mut current = start.map(ChunkId::cast::<u8>);
+        while let Some(id) = current {
+            // next_hint is in the same spot for every kind of chunk
+            current = self.load(id)?.get_ref().next();
+        }
+        Ok(())
+    }
+
+    /// how the FreeList rooted in lock table entry `freelist` picks extents.
+    pub fn freelist_policy(&self, freelist: usize) -> Result<Policy, Error> {
+        self.with_freelist(freelist, |list| list.policy())
+    }
+
+    /// changes how the FreeList rooted in lock table entry `freelist` picks extents,
+    /// this is stored in the lock table entry and stays after reopening.
+    pub fn set_freelist_policy(&self, freelist: usize, policy: Policy) -> Result<(), Error> {
+        self.with_freelist(freelist, |list| list.set_policy(policy))
+    }
+
+    /// every chunk of list is written to through a slice, not through get_mut.
+    fn mark_freelist_written(&self, list: &FreeList<u8, SIZE>) {
+        for (id, _chunk) in list {
+            self.mark_written(id);
+        }
+    }
+
+    /// the list starting at start, on the chunks of this superblock.
+    ///
+    /// safety: the list needs to be locked through its lock table entry,
+    /// and every other access to its chunks has to go through that entry.
+    /// writes through the SliceList don't go through get_mut,
+    /// call mark_written for every chunk you change.
+    pub(crate) unsafe fn slice_list<T: ChunkPod>(
+        &self,
+        start: Option<ChunkId<T>>,
+    ) -> SliceList<'_, T, SIZE> {
+        SliceList::from_raw(self.base(), self.len(), start)
+    }
+
+    /// chunk id is being written to without get_mut, see get_mut.
+    pub(crate) fn mark_written<T>(&self, id: ChunkId<T>) {
+        crate::checksum::mark_written(self, id.pos());
+    }
+
+    /// allocates count adjacent chunks from the FreeList rooted in lock table entry `freelist`.
+    /// see FreeList::allocate for the meaning of the inner return value.
+    /// returns Error::Corrupt if a chunk of the freelist does not match its checksum.
+    ///
+    /// blocks while someone else is using the freelist.
+    pub fn alloc_chunks<T>(
+        &self,
+        freelist: usize,
+        count: u32,
+    ) -> Result<Result<ChunkId<T>, (ChunkId<T>, u32)>, Error> {
+        self.with_freelist(freelist, |list| cast_alloc(list.allocate(count)))
+    }
+
+    /// returns count chunks starting at id to the FreeList rooted in lock table entry `freelist`.
+    /// returns Error::Corrupt if a chunk of the freelist does not match its checksum,
+    /// the chunks are not freed then.
+    ///
+    /// safety: same as FreeList::free, additionally don't access the chunks after freeing them.
+    pub unsafe fn free_chunks<T>(
+        &self,
+        freelist: usize,
+        id: ChunkId<T>,
+        count: u32,
+    ) -> Result<(), Error> {
+        self.with_freelist(freelist, |list| list.free(id.cast(), count))
+    }
+
+    /// returns chunk id, which is no longer part of any list, to the FreeList rooted in lock
+    /// table entry `freelist`.
+    /// if a snapshot can still see the chunk that is delayed until the last such snapshot is
+    /// dropped.
+    ///
+    /// safety: same as free_chunks.
+    pub unsafe fn release_chunk<T>(&self, freelist: usize, id: ChunkId<T>) -> Result<(), Error> {
+        if let Some(frozen) = self.frozen.lock().unwrap().get_mut(&id.pos()) {
+            frozen.release_to = Some(freelist);
+            return Ok(());
+        }
+        self.free_chunks(freelist, id, 1)
+    }
+
+    /// like get, but checks the chunk against its checksum first.
+    /// every chunk is only checked the first time it is loaded, chunks that have been changed
+    /// through get_mut or load_mut since opening are not checked at all.
+    /// always succeeds if the superblock has no checksums.
+    ///
+    /// chunks changed through anything else since the last flush are reported as corrupt,
+    /// flush before loading those.
+    ///
+    /// safety: same as get.
+    pub unsafe fn load<T>(&self, id: ChunkId<T>) -> Result<&MaybeUninit<Chunk<T, SIZE>>, Error> {
+        crate::checksum::verify_once(self, id.pos())?;
+        Ok(self.get(id))
+    }
+
+    /// like get_mut, but checks the chunk against its checksum first, see load.
+    ///
+    /// safety: same as get_mut.
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn load_mut<T>(
+        &self,
+        id: ChunkId<T>,
+    ) -> Result<&mut MaybeUninit<Chunk<T, SIZE>>, Error> {
+        crate::checksum::verify_once(self, id.pos())?;
+        Ok(self.get_mut(id))
     }
 
     /// safety: only ever call this with a pos that you know no one else is accessing.
-    /// you can ensure that for the first chunk by using the lock() and unlock() functionality
-    /// of this type.
+    /// you can ensure that for the first chunk by holding the SuperblockGuard of its list.
     ///
     /// only ever call this with a pos that is in bounds.
     ///
     /// don't ever call this a second time without releasing the first time.
-    pub unsafe fn get_mut<T>(&self, pos: usize) -> &mut MaybeUninit<Chunk<T>> {
-        let c = self.c as *mut Chunk<T> as *mut MaybeUninit<Chunk<T>>;
-        c.add(pos).as_mut().unwrap()
+    ///
+    /// does not check the checksum of the chunk, see load_mut.
+    /// its checksum is recomputed on the next flush. if you keep writing to the chunk after
+    /// that flush, call this again so the flush after that picks up the changes as well.
+    // handing out &mut from &self is the point, the locks make it sound.
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn get_mut<T>(&self, id: ChunkId<T>) -> &mut MaybeUninit<Chunk<T, SIZE>> {
+        // the chunk is about to change, its old checksum means nothing any more
+        crate::checksum::mark_written(self, id.pos());
+        let c = self.c as *mut Chunk<T, SIZE> as *mut MaybeUninit<Chunk<T, SIZE>>;
+        c.add(id.pos()).as_mut().unwrap()
     }
 
     /// safety: only ever call this with a pos that you know no one else is accessing.
-    /// you can ensure that for the first chunk by using the lock() and unlock() functionality
-    /// of this type.
+    /// you can ensure that for the first chunk by holding the SuperblockGuard of its list.
     ///
     /// only ever call this with a pos that is in bounds.
     ///
     /// you can safely call this multiple times.
-    pub unsafe fn get<T>(&self, pos: usize) -> &MaybeUninit<Chunk<T>> {
-        let c = self.c as *mut Chunk<T> as *mut MaybeUninit<Chunk<T>>;
-        c.add(pos).as_ref().unwrap()
+    ///
+    /// does not check the checksum of the chunk, see load.
+    ///
+    /// for ChunkPod types, get::<u8> and Chunk::view check that the chunk fits T instead
+    /// of trusting it to be a Chunk<T>.
+    pub unsafe fn get<T>(&self, id: ChunkId<T>) -> &MaybeUninit<Chunk<T, SIZE>> {
+        let c = self.c as *mut Chunk<T, SIZE> as *mut MaybeUninit<Chunk<T, SIZE>>;
+        c.add(id.pos()).as_ref().unwrap()
     }
 
     /// safety: uphold all safety guarantees of all the contained data, including the superblock
     /// this is easiest done by not accessing anything except things you explicitly have a pointer
     /// to.
-    pub unsafe fn as_mut(&self) -> *mut [Chunk<u8>] {
+    ///
+    /// checksums of chunks written through this are not updated, use get_mut for that.
+    pub unsafe fn as_mut(&self) -> *mut [Chunk<u8, SIZE>] {
         self.c
     }
 }
+
+/// FreeList::allocate for chunks of u8, as chunks of T.
+pub(crate) fn cast_alloc<T>(
+    r: Result<ChunkId<u8>, (ChunkId<u8>, u32)>,
+) -> Result<ChunkId<T>, (ChunkId<T>, u32)> {
+    r.map(ChunkId::cast).map_err(|(id, len)| (id.cast(), len))
+}
+
+/// A write-locked lock table entry, derefs to the (start, _) root stored in it.
+///
+/// The entry is unlocked when this is dropped, including during a panic.
+pub struct SuperblockGuard<'s, const SIZE: usize = DEFAULT_CHUNK_SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    superblock: &'s Superblock<SIZE>,
+    pos: usize,
+    root: &'s mut (usize, usize),
+}
+
+impl<'s, const SIZE: usize> SuperblockGuard<'s, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    /// the lock table entry this guards
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    /// walks the list starting at the guarded root, with write access.
+    /// the chunks are checked to fit T, see CursorMut::from_byteslice.
+    /// every chunk of the list counts as written to, see Superblock::flush.
+    ///
+    /// every chunk is loaded first, see Superblock::load.
+    /// returns Error::Corrupt for the first one that does not match its checksum.
+    ///
+    /// chunks seen by a snapshot are copied first, into chunks from the FreeList rooted in
+    /// lock table entry `freelist`, see make_mut.
+    ///
+    /// returns Error::Exhausted if the freelist runs out of chunks to copy into,
+    /// the chunks copied up to then stay copied.
+    ///
+    /// safety: every other access to the list has to go through this entry.
+    ///
+    /// panics if `freelist` is the guarded entry.
+    pub unsafe fn cursor_mut<T: ChunkPod>(
+        &mut self,
+        freelist: usize,
+    ) -> Result<CursorMut<'_, T, SIZE>, Error> {
+        self.superblock.load_list(self.start::<T>())?;
+        // making the last chunk writable makes every chunk in front of it writable
+        let (base, len) = (self.superblock.base(), self.superblock.len());
+        let last = Cursor::<T, SIZE>::from_raw(base, len, self.start()).last();
+        if let Some((last, _chunk)) = last {
+            self.make_mut(freelist, last)?;
+        }
+        // the cursor writes through its own pointers, not through get_mut
+        for (id, _chunk) in Cursor::<T, SIZE>::from_raw(base, len, self.start()) {
+            self.superblock.mark_written(id);
+        }
+        Ok(CursorMut::from_raw(base, len, self.start()))
+    }
+
+    /// the first chunk of the guarded list, None if it is empty.
+    pub fn start<T>(&self) -> Option<ChunkId<T>> {
+        ChunkId::from_link(self.root.0)
+    }
+
+    /// makes start the first chunk of the guarded list.
+    pub fn set_start<T>(&mut self, start: Option<ChunkId<T>>) {
+        self.root.0 = ChunkId::into_link(start);
+    }
+
+    /// makes chunk id of the guarded list safe to change while snapshots exist.
+    /// call this before changing a chunk, and change the returned chunk instead.
+    ///
+    /// if id, or a chunk in front of it, is seen by a snapshot it is copied into a chunk
+    /// from the FreeList rooted in lock table entry `freelist`, which replaces it in the list.
+    /// the original is released once the snapshots are gone.
+    /// returns id itself if nothing had to be copied.
+    ///
+    /// returns Error::Exhausted if the freelist runs out of chunks to copy into,
+    /// the chunks copied up to then stay copied.
+    ///
+    /// safety: id needs to be part of the guarded list, which is a list of chunks.
+    /// don't hold on to any reference into the list across this call.
+    ///
+    /// panics if `freelist` is the guarded entry.
+    pub unsafe fn make_mut<T>(
+        &mut self,
+        freelist: usize,
+        id: ChunkId<T>,
+    ) -> Result<ChunkId<T>, Error> {
+        crate::snapshot::make_mut(self.superblock, self.root, freelist, id)
+    }
+}
+
+impl<'s, const SIZE: usize> Deref for SuperblockGuard<'s, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    type Target = (usize, usize);
+    fn deref(&self) -> &Self::Target {
+        self.root
+    }
+}
+
+impl<'s, const SIZE: usize> DerefMut for SuperblockGuard<'s, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.root
+    }
+}
+
+impl<'s, const SIZE: usize> Drop for SuperblockGuard<'s, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    fn drop(&mut self) {
+        // pos has been locked on creation of the guard, and there is only one guard per lock.
+        unsafe { self.superblock.unlock(self.pos) }
+    }
+}
+
+/// A read-locked lock table entry, derefs to the (start, _) root stored in it.
+///
+/// Any number of these can exist for the same entry, but no SuperblockGuard.
+/// The entry is unlocked when the last one is dropped.
+pub struct SuperblockReadGuard<'s, const SIZE: usize = DEFAULT_CHUNK_SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    superblock: &'s Superblock<SIZE>,
+    pos: usize,
+    root: &'s (usize, usize),
+}
+
+impl<'s, const SIZE: usize> SuperblockReadGuard<'s, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    /// the lock table entry this guards
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    /// walks the list starting at the guarded root.
+    /// the chunks are checked to fit T, see Cursor::from_byteslice.
+    ///
+    /// every chunk is loaded first, see Superblock::load.
+    /// returns Error::Corrupt for the first one that does not match its checksum.
+    ///
+    /// safety: every other access to the list has to go through this entry.
+    pub unsafe fn cursor<T: ChunkPod>(&self) -> Result<Cursor<'_, T, SIZE>, Error> {
+        self.superblock.load_list(self.start::<T>())?;
+        let (base, len) = (self.superblock.base(), self.superblock.len());
+        Ok(Cursor::from_raw(base, len, self.start()))
+    }
+
+    /// the first chunk of the guarded list, None if it is empty.
+    pub fn start<T>(&self) -> Option<ChunkId<T>> {
+        ChunkId::from_link(self.root.0)
+    }
+}
+
+impl<'s, const SIZE: usize> Deref for SuperblockReadGuard<'s, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    type Target = (usize, usize);
+    fn deref(&self) -> &Self::Target {
+        self.root
+    }
+}
+
+impl<'s, const SIZE: usize> Drop for SuperblockReadGuard<'s, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    fn drop(&mut self) {
+        // pos has been read-locked on creation of the guard, once for every guard.
+        unsafe { self.superblock.unlock_read(self.pos) }
+    }
+}
+
+/// A SliceList locked through its lock table entry,
+/// taking its chunks from and returning them to a FreeList.
+/// derefs to the SliceList, the functions in here keep the root,
+/// the written chunks and the snapshots in sync with it.
+///
+/// RleList and SortedList are built on this.
+pub(crate) struct LockedList<'s, T, const SIZE: usize = DEFAULT_CHUNK_SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    start: &'s mut (usize, usize),
+    freelist: usize,
+    superblock: &'s Superblock<SIZE>,
+    list: SliceList<'s, T, SIZE>,
+}
+
+impl<'s, T: ChunkPod, const SIZE: usize> LockedList<'s, T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    /// safety: start is from locking the list, and all chunks reachable from it
+    /// need to be chunks of T. freelist is the index of the freelist.
+    ///
+    /// panics if freelist is the entry start is from.
+    pub(crate) unsafe fn new(
+        superblock: &'s Superblock<SIZE>,
+        start: &'s mut (usize, usize),
+        freelist: usize,
+    ) -> Self {
+        superblock.assert_other_freelist(freelist, start);
+        // the list is locked through start, so nobody else is accessing it.
+        let list = superblock.slice_list(ChunkId::from_link(start.0));
+        Self {
+            start,
+            freelist,
+            superblock,
+            list,
+        }
+    }
+
+    /// writes the first chunk of the list back to the root.
+    pub(crate) fn sync_start(&mut self) {
+        self.start.0 = ChunkId::into_link(self.list.start());
+    }
+
+    /// chunk id, marked as written.
+    ///
+    /// safety: id needs to be part of this list
+    /// and you can not hold on to any other reference to that chunk.
+    pub(crate) unsafe fn chunk_mut<'b>(&mut self, id: ChunkId<T>) -> &'b mut Chunk<T, SIZE> {
+        self.superblock.mark_written(id);
+        (self.list.get_mut(id) as *mut Chunk<T, SIZE>)
+            .as_mut()
+            .unwrap()
+    }
+
+    /// makes chunk id safe to change while snapshots exist, see SuperblockGuard::make_mut.
+    /// returns the chunk to change instead, and the chunk in front of it.
+    pub(crate) fn make_mut(
+        &mut self,
+        id: ChunkId<T>,
+    ) -> Result<(Option<ChunkId<T>>, ChunkId<T>), Error> {
+        // id is part of the list, which is locked through start
+        let id =
+            unsafe { crate::snapshot::make_mut(self.superblock, self.start, self.freelist, id) };
+        // chunks in front of id might have been replaced, the first one included.
+        // that also happens if copying fails halfway through.
+        self.list = unsafe { self.superblock.slice_list(ChunkId::from_link(self.start.0)) };
+        let id = id?;
+        let pre = self
+            .list
+            .chunks()
+            .map(|(current, _chunk)| current)
+            .take_while(|current| *current != id)
+            .last();
+        Ok((pre, id))
+    }
+
+    /// a new chunk from the freelist, marked as written.
+    /// returns Error::Exhausted if the freelist has none.
+    pub(crate) fn alloc_chunk(&mut self) -> Result<ChunkId<T>, Error> {
+        let new = match self.superblock.alloc_chunks(self.freelist, 1)? {
+            Ok(new) => new,
+            Err(_) => return Err(Error::Exhausted),
+        };
+        self.superblock.mark_written(new);
+        Ok(new)
+    }
+
+    /// removes the empty chunk id, that follows pre, from the list
+    /// and returns it to the freelist once no snapshot sees it any more.
+    /// the chunk is gone from the list even if returning it fails.
+    pub(crate) fn unlink(&mut self, pre: Option<ChunkId<T>>, id: ChunkId<T>) -> Result<(), Error> {
+        debug_assert_eq!(unsafe { self.list.get(id) }.len(), 0);
+        if let Some(pre) = pre {
+            self.superblock.mark_written(pre);
+        }
+        unsafe { self.list.unlink(pre, id) };
+        self.sync_start();
+        // id is not part of the list any more
+        unsafe { self.superblock.release_chunk(self.freelist, id) }
+    }
+}
+
+impl<'s, T, const SIZE: usize> Deref for LockedList<'s, T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    type Target = SliceList<'s, T, SIZE>;
+    fn deref(&self) -> &Self::Target {
+        &self.list
+    }
+}
+
+impl<'s, T, const SIZE: usize> DerefMut for LockedList<'s, T, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.list
+    }
+}
+
+impl<const SIZE: usize> Drop for Superblock<SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    fn drop(&mut self) {
+        // errors can't be reported from here, call flush() before dropping if you care.
+        // unmapping is done by the map itself.
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+fn temp_path(name: &str) -> std::path::PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("block-layer-{}-{}.img", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn persist() {
+    let path = temp_path("persist");
+    {
+        let s: Superblock = Superblock::create(&path, 8).unwrap();
+        assert_eq!(s.len(), 8);
+        let chunk = Chunk::initialize(unsafe { s.get_mut::<u64>(ChunkId::new(3)) });
+        for i in 0..100 {
+            chunk.push(i);
+        }
+        chunk.next_hint = 5;
+        s.flush().unwrap();
+    }
+    {
+        let s: Superblock = Superblock::open(&path).unwrap();
+        assert_eq!(s.len(), 8);
+        let chunk = unsafe { s.get::<u64>(ChunkId::new(3)).get_ref() };
+        assert_eq!(chunk.len(), 100);
+        assert_eq!(chunk[99], 99);
+        assert_eq!(chunk.next_hint, 5);
+    }
+    // the file already exists
+    assert!(Superblock::<CHUNK_SIZE>::create(&path, 8).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn header() {
+    let path = temp_path("header");
+    {
+        let s: Superblock = Superblock::create(&path, 8).unwrap();
+        assert_eq!(s.header(), &Header::current::<CHUNK_SIZE>(8, false));
+        let root = s.lock(s.header().freelist as usize).unwrap();
+        assert_eq!(root.0, FREELIST_CHUNK);
+    }
+
+    let s: Superblock = Superblock::open(&path).unwrap();
+    unsafe {
+        let header = s.get_mut::<Header>(ChunkId::new(HEADER_CHUNK)).get_mut();
+        header[0].version += 1;
+    }
+    drop(s);
+    match Superblock::<CHUNK_SIZE>::open(&path) {
+        Err(Error::Version(v)) => assert_eq!(v, VERSION + 1),
+        other => panic!("expected a version error, got {:?}", other.err()),
+    }
+
+    std::fs::write(&path, vec![0u8; CHUNK_SIZE * 8]).unwrap();
+    match Superblock::<CHUNK_SIZE>::open(&path) {
+        Err(Error::Magic(m)) => assert_eq!(m, [0; 8]),
+        other => panic!("expected a magic error, got {:?}", other.err()),
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn freelist_policy() {
+    let path = temp_path("freelist-policy");
+    let freelist;
+    let first: ChunkId<u8>;
+    {
+        let s: Superblock = Superblock::create(&path, 32).unwrap();
+        freelist = s.header().freelist as usize;
+        assert_eq!(s.freelist_policy(freelist).unwrap(), Policy::FirstFit);
+        s.set_freelist_policy(freelist, Policy::NextFit).unwrap();
+        first = s.alloc_chunks(freelist, 1).unwrap().unwrap();
+        let _second: ChunkId<u8> = s.alloc_chunks(freelist, 1).unwrap().unwrap();
+        unsafe { s.free_chunks(freelist, first, 1) }.unwrap();
+    }
+    let s: Superblock = Superblock::open(&path).unwrap();
+    assert_eq!(s.freelist_policy(freelist).unwrap(), Policy::NextFit);
+    // first fit would hand out the first chunk again
+    let third: ChunkId<u8> = s.alloc_chunks(freelist, 1).unwrap().unwrap();
+    assert_eq!(third.pos(), first.pos() + 2);
+    drop(s);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn big_chunks() {
+    // bigger than a page, so the mapping needs aligning
+    const BIG: usize = 65536;
+    let s = Superblock::<BIG>::anonymous(16).unwrap();
+    let id: ChunkId<u64> = s
+        .alloc_chunks(s.header().freelist as usize, 1)
+        .unwrap()
+        .unwrap();
+    Chunk::initialize(unsafe { s.get_mut(id) }).push(1);
+    drop(s);
+
+    let path = temp_path("big-chunks");
+    let id: ChunkId<u64>;
+    {
+        let s = Superblock::<BIG>::create(&path, 16).unwrap();
+        id = s
+            .alloc_chunks(s.header().freelist as usize, 1)
+            .unwrap()
+            .unwrap();
+        let chunk = Chunk::initialize(unsafe { s.get_mut(id) });
+        for i in 0..1000 {
+            chunk.push(i);
+        }
+    }
+    let s = Superblock::<BIG>::open(&path).unwrap();
+    assert_eq!(s.len(), 16);
+    let chunk = unsafe { s.get(id).get_ref() };
+    assert_eq!(chunk.len(), 1000);
+    assert_eq!(chunk[999], 999);
+    drop(s);
+    assert!(Superblock::<CHUNK_SIZE>::open(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn locking() {
+    use std::sync::Arc;
+
+    let s: Arc<Superblock> = Arc::new(Superblock::anonymous(8).unwrap());
+    let mut guard = s.lock(1).unwrap();
+    assert_eq!(guard.pos(), 1);
+    guard.0 = 42;
+    assert!(s.lock(1).is_none());
+    assert!(s.try_lock_for(1, Duration::from_millis(5)).is_none());
+    // other entries are not affected
+    assert!(s.lock(2).is_some());
+    drop(guard);
+    assert_eq!(s.lock(1).unwrap().0, 42);
+
+    // the other thread has to wait until the guard is gone
+    let guard = s.write(1);
+    let other = Arc::clone(&s);
+    let waiter = std::thread::spawn(move || {
+        let mut guard = other.write(1);
+        guard.0 += 1;
+    });
+    std::thread::sleep(Duration::from_millis(20));
+    assert_eq!(guard.0, 42);
+    drop(guard);
+    waiter.join().unwrap();
+    assert_eq!(s.try_lock_for(1, Duration::from_secs(1)).unwrap().0, 43);
+
+    // a panic while holding the lock does not leave it locked
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let _guard = s.lock(1).unwrap();
+        panic!("oops");
+    }));
+    assert!(res.is_err());
+    assert!(s.lock(1).is_some());
+}
+
+#[test]
+fn read_write() {
+    use std::sync::Arc;
+
+    let s: Arc<Superblock> = Arc::new(Superblock::anonymous(8).unwrap());
+    // a list of two chunks, 3 -> 5
+    {
+        let mut root = s.write(1);
+        let first = Chunk::initialize(unsafe { s.get_mut::<u64>(ChunkId::new(3)) });
+        first.push(1);
+        first.next_hint = 5;
+        let second = Chunk::initialize(unsafe { s.get_mut::<u64>(ChunkId::new(5)) });
+        second.push(2);
+        root.0 = 3;
+        let freelist = s.header().freelist as usize;
+        let ids: Vec<_> = unsafe { root.cursor_mut::<u64>(freelist) }
+            .unwrap()
+            .map(|(id, _chunk)| id.pos())
+            .collect();
+        assert_eq!(ids, vec![3, 5]);
+    }
+
+    let reader = s.read(1);
+    let other = s.try_read(1).unwrap();
+    assert!(s.lock(1).is_none());
+    assert!(s.try_lock_for(1, Duration::from_millis(5)).is_none());
+    let walk = |guard: &SuperblockReadGuard| -> Vec<u64> {
+        unsafe { guard.cursor::<u64>() }
+            .unwrap()
+            .elements()
+            .copied()
+            .collect()
+    };
+    assert_eq!(walk(&reader), vec![1, 2]);
+    assert_eq!(walk(&other), vec![1, 2]);
+
+    // readers in other threads don't have to wait
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let s = Arc::clone(&s);
+            std::thread::spawn(move || {
+                let guard = s.try_read_for(1, Duration::from_secs(1)).unwrap();
+                let sum: u64 = unsafe { guard.cursor::<u64>() }.unwrap().elements().sum();
+                sum
+            })
+        })
+        .collect();
+    for t in threads {
+        assert_eq!(t.join().unwrap(), 3);
+    }
+
+    // the writer has to wait for the last reader
+    drop(reader);
+    assert!(s.lock(1).is_none());
+    drop(other);
+    let writer = s.lock(1).unwrap();
+    assert!(s.try_read(1).is_none());
+    assert!(s.try_read_for(1, Duration::from_millis(5)).is_none());
+    drop(writer);
+    assert_eq!(s.read(1).0, 3);
+}
+
+#[test]
+fn contention() {
+    use std::sync::Arc;
+
+    // lots of threads waiting on the same entries, none of them may miss an unlock
+    let s: Arc<Superblock> = Arc::new(Superblock::anonymous(8).unwrap());
+    let threads: Vec<_> = (0..8)
+        .map(|t| {
+            let s = Arc::clone(&s);
+            std::thread::spawn(move || {
+                for _ in 0..1000 {
+                    let pos = 1 + t % 2;
+                    if t < 6 {
+                        s.write(pos).1 += 1;
+                    } else {
+                        let _ = s.read(pos).1;
+                    }
+                }
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+    assert_eq!(s.read(1).1, 3000);
+    assert_eq!(s.read(2).1, 3000);
+
+    // a timed wait is woken by the unlock, long before its timeout
+    let guard = s.write(1);
+    let other = Arc::clone(&s);
+    let waiter = std::thread::spawn(move || {
+        let start = Instant::now();
+        assert!(other.try_lock_for(1, Duration::from_secs(60)).is_some());
+        start.elapsed()
+    });
+    std::thread::sleep(Duration::from_millis(20));
+    drop(guard);
+    assert!(waiter.join().unwrap() < Duration::from_secs(30));
+}
@@ -0,0 +1,451 @@
+//! Write-ahead log for crash-consistent updates of a Superblock.
+//!
+//! This is an undo log: before a chunk is changed in place its old contents are copied into the
+//! log and flushed to disk. Once all changes of a transaction are flushed the log is cleared.
+//! If the process dies in between, the saved chunks are copied back on the next open,
+//! so a transaction either happens completely or not at all.
+//!
+//! The log lives in the last chunks of the superblock.
+//! The first of those is the directory, a Chunk<u64> holding the home position of every saved
+//! chunk, the rest are the slots the saved chunks are copied into.
+//! The directory's len is only increased after the slot has been flushed,
+//! so a torn copy is never restored.
+//!
+//! There is only one log, so only one transaction can run at a time.
+//! Transactions don't isolate anything, they only protect against crashes.
+//! Changes outside of a transaction are not logged at all.
+use crate::base_chunk::ChunkId;
+use crate::base_chunk::ChunkSize;
+use crate::base_chunk::Size;
+use crate::base_chunk::DEFAULT_CHUNK_SIZE;
+use crate::superblock::Error;
+use crate::superblock::Superblock;
+use crate::superblock::LOCK_TABLE_CHUNK;
+use std::io;
+use std::sync::MutexGuard;
+use std::sync::PoisonError;
+type Chunk<T, const SIZE: usize = DEFAULT_CHUNK_SIZE> = crate::base_chunk::Chunk<T, usize, SIZE>;
+
+/// the most chunks a single transaction can save, no matter how big the superblock is.
+pub const MAX_LOG_SLOTS: usize = 256;
+
+/// how many slots the log of a superblock with n_chunks chunks has.
+pub(crate) fn log_slots(n_chunks: usize) -> usize {
+    (n_chunks / 16).max(1).min(MAX_LOG_SLOTS)
+}
+
+/// writes an empty log.
+pub(crate) fn init<const SIZE: usize>(superblock: &Superblock<SIZE>)
+where
+    Size<SIZE>: ChunkSize,
+{
+    let log = superblock.header().log as usize;
+    // the log is not in use yet, nobody else is accessing it
+    Chunk::initialize(unsafe { superblock.get_mut::<u64>(ChunkId::new(log)) });
+}
+
+/// copies all saved chunks back to where they came from and clears the log.
+/// returns true if there was anything to restore.
+///
+/// all lock table entries are unlocked afterwards, whoever held them when the file was last
+/// closed is gone.
+pub(crate) fn recover<const SIZE: usize>(superblock: &Superblock<SIZE>) -> io::Result<bool>
+where
+    Size<SIZE>: ChunkSize,
+{
+    // nobody else can have access to the superblock yet
+    let running = superblock
+        .log
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    let restored = Log::new(superblock, running).restore()?;
+    unsafe { superblock.reset_locks() };
+    superblock.flush_chunks(ChunkId::<u8>::new(LOCK_TABLE_CHUNK), 1)?;
+    Ok(restored)
+}
+
+/// access to the log chunks, only exists while Superblock::log is held.
+struct Log<'s, const SIZE: usize>
+where
+    Size<SIZE>: ChunkSize,
+{
+    superblock: &'s Superblock<SIZE>,
+    _running: MutexGuard<'s, ()>,
+    dir: usize,
+    slots: usize,
+}
+
+impl<'s, const SIZE: usize> Log<'s, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    fn new(superblock: &'s Superblock<SIZE>, running: MutexGuard<'s, ()>) -> Self {
+        let header = superblock.header();
+        Self {
+            superblock,
+            _running: running,
+            dir: header.log as usize,
+            slots: header.log_slots as usize,
+        }
+    }
+
+    fn dir(&self) -> &Chunk<u64, SIZE> {
+        // we hold the log mutex, so nobody else is accessing the log chunks
+        unsafe { self.superblock.get::<u64>(ChunkId::new(self.dir)).get_ref() }
+    }
+
+    /// the directory for changing it, which makes the next flush checksum it again.
+    fn dir_mut(&mut self) -> &mut Chunk<u64, SIZE> {
+        // we hold the log mutex, so nobody else is accessing the log chunks
+        unsafe {
+            self.superblock
+                .get_mut::<u64>(ChunkId::new(self.dir))
+                .get_mut()
+        }
+    }
+
+    /// copies pos into the next free slot, unless it has been saved before.
+    fn save(&mut self, pos: usize) -> Result<(), Error> {
+        if pos >= self.superblock.len() || pos >= self.dir {
+            panic!("tried to save a chunk that is out of bounds or part of the log");
+        }
+        if self.dir().contains(&(pos as u64)) {
+            return Ok(());
+        }
+        let len = self.dir().len();
+        if len >= self.slots || len >= self.dir().capacity() {
+            return Err(Error::LogFull);
+        }
+
+        let slot = self.dir + 1 + len;
+        // the slot is not in use, the caller is responsible for pos
+        unsafe { self.superblock.copy_chunk(pos, slot) };
+        self.superblock.flush_chunks(ChunkId::<u8>::new(slot), 1)?;
+        // only now the saved chunk becomes part of the log
+        self.dir_mut().push(pos as u64);
+        self.superblock
+            .flush_chunks(ChunkId::<u64>::new(self.dir), 1)?;
+        Ok(())
+    }
+
+    /// forgets all saved chunks.
+    fn clear(&mut self) -> io::Result<()> {
+        if self.dir().len() == 0 {
+            return Ok(());
+        }
+        while let Some(_) = self.dir_mut().pop() {}
+        self.superblock
+            .flush_chunks(ChunkId::<u64>::new(self.dir), 1)
+    }
+
+    /// copies all saved chunks back, then clears the log.
+    /// returns true if there was anything to restore.
+    fn restore(&mut self) -> io::Result<bool> {
+        let len = self.dir().len();
+        if len == 0 {
+            return Ok(false);
+        }
+        for i in 0..len {
+            let pos = self.dir()[i] as usize;
+            // the caller is responsible for nobody accessing the saved chunks
+            if pos == LOCK_TABLE_CHUNK {
+                // the lock words were saved while being held, restoring them would leave them locked
+                unsafe { self.superblock.restore_lock_table(self.dir + 1 + i) };
+            } else {
+                unsafe { self.superblock.copy_chunk(self.dir + 1 + i, pos) };
+            }
+            // only flush what has been restored, flushing everything would take the
+            // contents of all other chunks as correct.
+            self.superblock.flush_chunks(ChunkId::<u8>::new(pos), 1)?;
+        }
+        // a crash before the log is cleared just restores again, which is fine
+        self.clear()?;
+        Ok(true)
+    }
+}
+
+/// A set of changes to a Superblock that survives crashes as a whole, or not at all.
+///
+/// Call protect on every chunk before changing it,
+/// the chunks are changed in place as usual.
+/// Chunks that were free when the transaction started don't need to be protected,
+/// they are free again if the transaction is rolled back.
+///
+/// Dropping a transaction without committing it rolls it back.
+pub struct Transaction<'s, const SIZE: usize = DEFAULT_CHUNK_SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    log: Log<'s, SIZE>,
+    done: bool,
+}
+
+impl<'s, const SIZE: usize> Transaction<'s, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    /// starts a transaction, waiting for the running one to finish if there is one.
+    pub(crate) fn new(superblock: &'s Superblock<SIZE>) -> Self {
+        // a transaction that panicked has been rolled back by its drop, the log is consistent.
+        let running = superblock
+            .log
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        Self {
+            log: Log::new(superblock, running),
+            done: false,
+        }
+    }
+
+    /// saves count chunks starting at id, so they can be restored if the transaction
+    /// does not finish. call this before changing them.
+    /// saving a chunk a second time does nothing.
+    ///
+    /// returns Error::LogFull if the log has no space left,
+    /// the chunks saved up to that point stay saved.
+    ///
+    /// panics if a chunk is out of bounds or part of the log.
+    pub fn protect<T>(&mut self, id: ChunkId<T>, count: usize) -> Result<(), Error> {
+        for pos in id.pos()..id.pos() + count {
+            self.log.save(pos)?;
+        }
+        Ok(())
+    }
+
+    /// like Superblock::alloc_chunks, but protects the chunks of the freelist first.
+    pub fn alloc_chunks<T>(
+        &mut self,
+        freelist: usize,
+        count: u32,
+    ) -> Result<Result<ChunkId<T>, (ChunkId<T>, u32)>, Error> {
+        let superblock = self.log.superblock;
+        let log = &mut self.log;
+        superblock.with_freelist(freelist, |list| {
+            Self::protect_freelist(log, list)?;
+            Ok(crate::superblock::cast_alloc(list.allocate(count)))
+        })?
+    }
+
+    /// like Superblock::free_chunks, but protects the chunks of the freelist first.
+    ///
+    /// safety: same as Superblock::free_chunks.
+    pub unsafe fn free_chunks<T>(
+        &mut self,
+        freelist: usize,
+        id: ChunkId<T>,
+        count: u32,
+    ) -> Result<(), Error> {
+        let superblock = self.log.superblock;
+        let log = &mut self.log;
+        superblock.with_freelist(freelist, |list| {
+            Self::protect_freelist(log, list)?;
+            list.free(id.cast(), count);
+            Ok(())
+        })?
+    }
+
+    /// saves every chunk of list, and the lock table which holds its root.
+    /// chunks the list takes for itself were free, they don't need saving.
+    fn protect_freelist(
+        log: &mut Log<SIZE>,
+        list: &crate::freelist::FreeList<u8, SIZE>,
+    ) -> Result<(), Error> {
+        log.save(LOCK_TABLE_CHUNK)?;
+        for (id, _chunk) in list {
+            log.save(id.pos())?;
+        }
+        Ok(())
+    }
+
+    /// writes all changes to disk, after this they survive a crash.
+    pub fn commit(mut self) -> io::Result<()> {
+        self.done = true;
+        self.log.superblock.flush()?;
+        self.log.clear()
+    }
+
+    /// undoes all changes to protected chunks.
+    pub fn rollback(mut self) -> io::Result<()> {
+        self.done = true;
+        self.log.restore().map(|_| ())
+    }
+}
+
+impl<'s, const SIZE: usize> Drop for Transaction<'s, SIZE>
+where
+    Size<SIZE>: ChunkSize,
+{
+    fn drop(&mut self) {
+        if !self.done {
+            // errors can't be reported from here, the log is restored on the next open anyway.
+            let _ = self.log.restore();
+        }
+    }
+}
+
+#[test]
+fn crash_recovery() {
+    let path = std::env::temp_dir().join(format!("block-layer-wal-{}.img", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let freelist;
+    let allocated: ChunkId<u64>;
+    {
+        let s: Superblock = Superblock::create(&path, 64).unwrap();
+        freelist = s.header().freelist as usize;
+        let data: ChunkId<u64> = s.alloc_chunks(freelist, 1).unwrap().unwrap();
+        let chunk = Chunk::initialize(unsafe { s.get_mut(data) });
+        chunk.push(1);
+        s.flush().unwrap();
+
+        // committed changes stay
+        let mut tx = s.transaction();
+        tx.protect(data, 1).unwrap();
+        unsafe { s.get_mut(data).get_mut() }.push(2);
+        tx.commit().unwrap();
+
+        // rolled back changes don't
+        let mut tx = s.transaction();
+        tx.protect(data, 1).unwrap();
+        unsafe { s.get_mut(data).get_mut() }.push(3);
+        drop(tx);
+        assert_eq!(&unsafe { s.get(data).get_ref() }[..], &[1, 2]);
+
+        // neither happens on a crash
+        let mut tx = s.transaction();
+        tx.protect(data, 1).unwrap();
+        let chunk = unsafe { s.get_mut(data).get_mut() };
+        chunk.pop();
+        chunk.next_hint = 1234;
+        allocated = tx.alloc_chunks(freelist, 3).unwrap().unwrap();
+        unsafe { tx.free_chunks(freelist, data, 1) }.unwrap();
+        std::mem::forget(tx);
+        s.flush().unwrap();
+
+        // keep the data chunk for later
+        assert_eq!(data.pos(), 3);
+    }
+
+    let s: Superblock = Superblock::open(&path).unwrap();
+    let chunk = unsafe { s.get::<u64>(ChunkId::new(3)).get_ref() };
+    assert_eq!(&chunk[..], &[1, 2]);
+    assert!(!chunk.has_next());
+    // the freelist is back to before the transaction, and not locked
+    assert_eq!(s.alloc_chunks(freelist, 3).unwrap(), Ok(allocated));
+    let root = s.lock(freelist).unwrap();
+    drop(root);
+
+    // the log has limited space
+    let mut tx = s.transaction();
+    let slots = s.header().log_slots as usize;
+    assert!(tx.protect(ChunkId::<u8>::new(10), slots).is_ok());
+    match tx.protect(ChunkId::<u8>::new(10 + slots), 1) {
+        Err(Error::LogFull) => {}
+        other => panic!("expected the log to be full, got {:?}", other),
+    }
+    tx.commit().unwrap();
+
+    drop(s);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn stale_locks() {
+    let path = std::env::temp_dir().join(format!("block-layer-stale-{}.img", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    {
+        let s: Superblock = Superblock::create(&path, 64).unwrap();
+        let freelist = s.header().freelist as usize;
+        // a panic inside a transaction rolls it back and does not block the next one
+        let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _tx = s.transaction();
+            panic!("in transaction");
+        }));
+        assert!(r.is_err());
+        s.transaction().commit().unwrap();
+
+        // crash while holding locks outside of any transaction
+        std::mem::forget(s.write(freelist));
+        std::mem::forget(s.try_read(freelist + 1).unwrap());
+    }
+    let s: Superblock = Superblock::open(&path).unwrap();
+    let freelist = s.header().freelist as usize;
+    assert!(s.lock(freelist).is_some());
+    assert!(s.lock(freelist + 1).is_some());
+    drop(s);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn torn_log() {
+    let path = std::env::temp_dir().join(format!("block-layer-torn-{}.img", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let (first, second): (ChunkId<u64>, ChunkId<u64>);
+    {
+        let s: Superblock = Superblock::create(&path, 64).unwrap();
+        let freelist = s.header().freelist as usize;
+        first = s.alloc_chunks(freelist, 1).unwrap().unwrap();
+        second = s.alloc_chunks(freelist, 1).unwrap().unwrap();
+        Chunk::initialize(unsafe { s.get_mut(first) }).push(1);
+        Chunk::initialize(unsafe { s.get_mut(second) }).push(2);
+        s.flush().unwrap();
+
+        let mut tx = s.transaction();
+        tx.protect(first, 1).unwrap();
+        let chunk = unsafe { s.get_mut(first).get_mut() };
+        chunk[0] = 10;
+        // crash while saving the second chunk: its slot is written,
+        // but the directory entry that would make it part of the log is not.
+        let dir = s.header().log as usize;
+        let slot = Chunk::initialize(unsafe { s.get_mut::<u64>(ChunkId::new(dir + 2)) });
+        slot.push(1234);
+        slot.next_hint = 1234;
+        std::mem::forget(tx);
+        s.flush().unwrap();
+    }
+
+    let s: Superblock = Superblock::open(&path).unwrap();
+    // the saved chunk is restored, the torn slot is ignored
+    assert_eq!(&unsafe { s.get(first).get_ref() }[..], &[1]);
+    assert_eq!(&unsafe { s.get(second).get_ref() }[..], &[2]);
+    assert!(!unsafe { s.get(second).get_ref() }.has_next());
+    // and the log is empty again
+    let mut tx = s.transaction();
+    tx.protect(second, 1).unwrap();
+    let chunk = unsafe { s.get_mut(second).get_mut() };
+    chunk[0] = 20;
+    tx.commit().unwrap();
+    drop(s);
+
+    let s: Superblock = Superblock::open(&path).unwrap();
+    assert_eq!(&unsafe { s.get(first).get_ref() }[..], &[1]);
+    assert_eq!(&unsafe { s.get(second).get_ref() }[..], &[20]);
+    drop(s);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn drop_rollback() {
+    let path = std::env::temp_dir().join(format!("block-layer-drop-{}.img", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut s: Superblock = Superblock::create(&path, 64).unwrap();
+    let freelist = s.header().freelist as usize;
+    let kept: ChunkId<u64> = s.alloc_chunks(freelist, 1).unwrap().unwrap();
+    Chunk::initialize(unsafe { s.get_mut(kept) }).push(1);
+    s.write(1).set_start(Some(kept));
+    s.flush().unwrap();
+    let before = s.check();
+    assert!(before.is_ok(), "{:?}", before);
+
+    let mut tx = s.transaction();
+    let new: ChunkId<u64> = tx.alloc_chunks(freelist, 2).unwrap().unwrap();
+    unsafe { tx.free_chunks(freelist, kept, 1) }.unwrap();
+    drop(tx);
+
+    // kept is not free and new is free again
+    let after = s.check();
+    assert!(after.is_ok(), "{:?}", after);
+    assert_eq!(after.free, before.free);
+    assert_eq!(s.alloc_chunks(freelist, 2).unwrap(), Ok(new));
+    drop(s);
+    std::fs::remove_file(&path).unwrap();
+}